@@ -0,0 +1,217 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use globibot_core::{
+    events::{Event, EventType},
+    serenity::all::GuildId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::config;
+
+// Keys holding what users wrote, in events as well as in RPC arguments
+const REDACTED_KEYS: &[&str] = &[
+    "content",
+    "new_content",
+    "embeds",
+    "attachments",
+    "message_snapshots",
+];
+const REDACTED: &str = "<redacted>";
+
+static ACTIVITY: OnceLock<ActivityLog> = OnceLock::new();
+
+struct ActivityLog {
+    history: parking_lot::Mutex<VecDeque<Arc<Entry>>>,
+    capacity: usize,
+    redact_content: bool,
+    tx: broadcast::Sender<Arc<Entry>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    Event {
+        at: SystemTime,
+        event_type: EventType,
+        guild_id: Option<GuildId>,
+        plugin: Option<String>,
+        event: Value,
+    },
+    Rpc {
+        at: SystemTime,
+        plugin: String,
+        method: String,
+        request: Value,
+        duration: Duration,
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Filter {
+    plugin: Option<String>,
+    guild: Option<GuildId>,
+    event_type: Option<EventType>,
+}
+
+pub fn init(config: &config::Activity) {
+    if !config.enabled {
+        return;
+    }
+
+    let log = ActivityLog {
+        history: parking_lot::Mutex::new(VecDeque::with_capacity(config.history)),
+        capacity: config.history,
+        redact_content: config.redact_content,
+        tx: broadcast::channel(1 << 8).0,
+    };
+    ACTIVITY
+        .set(log)
+        .unwrap_or_else(|_| panic!("Activity log should only be initialized once"));
+}
+
+pub fn record_event(event: &Event, target: Option<&str>) {
+    let Some(log) = ACTIVITY.get() else {
+        return;
+    };
+
+    let (guild_id, source) = match event {
+        Event::MessageCreate { message } | Event::DirectMessageCreate { message } => {
+            (message.guild_id, None)
+        }
+        Event::InteractionCreate { interaction } => (interaction.guild_id, None),
+        Event::ThreadCreate { thread } | Event::ThreadUpdate { thread } => {
+            (Some(thread.guild_id), None)
+        }
+        Event::ThreadDelete { thread } => (Some(thread.guild_id), None),
+        Event::PollVoteAdd { vote } => (vote.guild_id, None),
+        Event::PollVoteRemove { vote } => (vote.guild_id, None),
+        Event::ScheduledEventUserAdd { subscription } => (Some(subscription.guild_id), None),
+        Event::ScheduledEventUserRemove { subscription } => (Some(subscription.guild_id), None),
+        Event::Topic { source, .. } | Event::PluginCall { source, .. } => (None, Some(source)),
+        Event::MessageDelete { .. } | Event::ScheduledJob { .. } | Event::Shutdown { .. } => {
+            (None, None)
+        }
+    };
+
+    log.push(Entry::Event {
+        at: SystemTime::now(),
+        event_type: event.ty(),
+        guild_id,
+        plugin: target.or(source.map(String::as_str)).map(str::to_owned),
+        event: log.redact(serde_json::to_value(event).unwrap_or_default()),
+    });
+}
+
+// Requests are consumed by the server, they have to be captured before being handled
+pub fn capture_request(request: &impl Serialize) -> Option<Value> {
+    let log = ACTIVITY.get()?;
+    Some(log.redact(serde_json::to_value(request).unwrap_or_default()))
+}
+
+pub fn record_rpc(
+    plugin: &str,
+    method: &str,
+    request: Value,
+    duration: Duration,
+    error: Option<String>,
+) {
+    let Some(log) = ACTIVITY.get() else {
+        return;
+    };
+
+    log.push(Entry::Rpc {
+        at: SystemTime::now(),
+        plugin: plugin.to_owned(),
+        method: method.to_owned(),
+        request,
+        duration,
+        error,
+    });
+}
+
+pub fn history(filter: &Filter) -> Option<Vec<Arc<Entry>>> {
+    let log = ACTIVITY.get()?;
+    let history = log.history.lock();
+    Some(
+        history
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect(),
+    )
+}
+
+pub fn subscribe() -> Option<broadcast::Receiver<Arc<Entry>>> {
+    Some(ACTIVITY.get()?.tx.subscribe())
+}
+
+impl ActivityLog {
+    fn push(&self, entry: Entry) {
+        let entry = Arc::new(entry);
+
+        let mut history = self.history.lock();
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        if self.capacity > 0 {
+            history.push_back(Arc::clone(&entry));
+        }
+        drop(history);
+
+        self.tx.send(entry).ok();
+    }
+
+    fn redact(&self, mut value: Value) -> Value {
+        if self.redact_content {
+            redact(&mut value);
+        }
+        value
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match entry {
+            Entry::Event {
+                event_type,
+                guild_id,
+                plugin,
+                ..
+            } => {
+                self.plugin
+                    .as_ref()
+                    .is_none_or(|wanted| plugin.as_ref() == Some(wanted))
+                    && self.guild.is_none_or(|wanted| *guild_id == Some(wanted))
+                    && self.event_type.is_none_or(|wanted| *event_type == wanted)
+            }
+            Entry::Rpc { plugin, .. } => {
+                self.plugin.as_ref().is_none_or(|wanted| plugin == wanted)
+                    && self.guild.is_none()
+                    && self.event_type.is_none()
+            }
+        }
+    }
+}
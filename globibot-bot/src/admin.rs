@@ -0,0 +1,368 @@
+use std::{collections::VecDeque, path::PathBuf, sync::Arc, time::SystemTime};
+
+use axum::{
+    BoxError, Extension, Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response, Sse, sse::Event},
+    routing::{get, post},
+};
+use futures::{Stream, StreamExt, future};
+use globibot_core::{
+    events::EventType,
+    serenity::all::{ChannelId, CreateMessage},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::{
+    activity, config,
+    rpc::Services,
+    supervisor::{LogLine, Supervisor},
+};
+
+const AUDIT_HISTORY: usize = 500;
+const DEFAULT_TEST_MESSAGE: &str = "Test message from the Globibot admin API";
+
+#[derive(Clone)]
+pub struct Admin {
+    // (name, token) pairs, actions are audited under the name
+    tokens: Arc<Vec<(String, String)>>,
+    services: Services,
+    supervisor: Supervisor,
+    audit: AuditLog,
+}
+
+#[derive(Clone)]
+struct AuditLog {
+    entries: Arc<parking_lot::Mutex<VecDeque<AuditEntry>>>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AuditEntry {
+    at: SystemTime,
+    actor: String,
+    action: &'static str,
+    target: String,
+    succeeded: bool,
+    detail: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Actor(String);
+
+#[derive(Debug, Deserialize)]
+struct EventFilter {
+    event_type: EventType,
+    muted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TestMessage {
+    content: Option<String>,
+}
+
+impl Admin {
+    pub fn new(config: &config::Admin, services: Services, supervisor: Supervisor) -> Option<Self> {
+        if config.tokens.is_empty() {
+            return None;
+        }
+
+        let tokens = config
+            .tokens
+            .iter()
+            .map(|(name, token)| (name.clone(), token.expose().to_owned()))
+            .collect();
+
+        Some(Self {
+            tokens: Arc::new(tokens),
+            services,
+            supervisor,
+            audit: AuditLog {
+                entries: <_>::default(),
+                path: config.audit_path.clone(),
+            },
+        })
+    }
+
+    fn actor(&self, token: &str) -> Option<Actor> {
+        self.tokens
+            .iter()
+            .find(|(_, expected)| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+            .map(|(name, _)| Actor(name.clone()))
+    }
+
+    async fn audit(
+        &self,
+        Actor(actor): Actor,
+        action: &'static str,
+        target: String,
+        result: Result<Option<String>, (StatusCode, String)>,
+    ) -> Response {
+        let (status, succeeded, detail) = match result {
+            Ok(detail) => (StatusCode::OK, true, detail),
+            Err((status, detail)) => (status, false, Some(detail)),
+        };
+        let entry = AuditEntry {
+            at: SystemTime::now(),
+            actor,
+            action,
+            target,
+            succeeded,
+            detail,
+        };
+        self.audit.record(&entry).await;
+
+        (status, Json(entry)).into_response()
+    }
+}
+
+impl AuditLog {
+    async fn record(&self, entry: &AuditEntry) {
+        info!(
+            target: "globibot_bot::audit",
+            "'{actor}' {action} '{target}': {outcome}",
+            actor = entry.actor,
+            action = entry.action,
+            target = entry.target,
+            outcome = if entry.succeeded { "ok" } else { "failed" },
+        );
+
+        {
+            let mut entries = self.entries.lock();
+            if entries.len() == AUDIT_HISTORY {
+                entries.pop_front();
+            }
+            entries.push_back(entry.clone());
+        }
+
+        if let Some(path) = &self.path
+            && let Err(why) = append_line(path, entry).await
+        {
+            warn!("Failed to write audit log to '{}': {why}", path.display());
+        }
+    }
+}
+
+pub fn routes<S: Clone + Send + Sync + 'static>(admin: Admin) -> Router<S> {
+    Router::new()
+        .route("/audit", get(audit_log))
+        // Entries carry message contents and RPC payloads, so they're for admins only
+        .route("/activity", get(activity_history))
+        .route("/activity/sse", get(stream_activity))
+        .route("/plugins/{id}/disconnect", post(disconnect_plugin))
+        .route("/plugins/{id}/pause", post(pause_plugin))
+        .route("/plugins/{id}/resume", post(resume_plugin))
+        .route("/plugins/{id}/filters", post(set_event_filter))
+        .route("/plugins/{id}/commands/sync", post(sync_commands))
+        .route("/channels/{id}/test-message", post(send_test_message))
+        // Plugins may well log secrets or user data
+        .route("/supervisor/{id}/logs", get(supervised_plugin_logs))
+        .route("/supervisor/{id}/enable", post(enable_plugin))
+        .route("/supervisor/{id}/disable", post(disable_plugin))
+        .layer(middleware::from_fn_with_state(admin.clone(), authenticate))
+        .with_state(admin)
+}
+
+async fn authenticate(
+    State(admin): State<Admin>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let Some(actor) = token.and_then(|token| admin.actor(token)) else {
+        warn!(
+            "Rejected unauthenticated admin request to {}",
+            request.uri()
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    request.extensions_mut().insert(actor);
+    Ok(next.run(request).await)
+}
+
+async fn audit_log(State(admin): State<Admin>) -> Json<Vec<AuditEntry>> {
+    Json(admin.audit.entries.lock().iter().cloned().collect())
+}
+
+async fn activity_history(
+    Query(filter): Query<activity::Filter>,
+) -> Result<Json<Vec<Arc<activity::Entry>>>, StatusCode> {
+    activity::history(&filter)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn stream_activity(
+    Query(filter): Query<activity::Filter>,
+) -> Result<Sse<impl Stream<Item = Result<Event, BoxError>>>, StatusCode> {
+    use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+
+    let rx = activity::subscribe().ok_or(StatusCode::NOT_FOUND)?;
+    let entry_stream = BroadcastStream::new(rx).filter_map(move |entry| {
+        let event = match entry {
+            Ok(entry) if filter.matches(&entry) => Event::default().json_data(entry),
+            Ok(_) => return future::ready(None),
+            // Slow dashboards miss entries rather than slowing down the bot
+            Err(BroadcastStreamRecvError::Lagged(_)) => return future::ready(None),
+        };
+        future::ready(Some(event.map_err(BoxError::from)))
+    });
+    Ok(Sse::new(entry_stream))
+}
+
+async fn disconnect_plugin(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let rpc = admin.services.rpc_clients.disconnect(&id);
+    let events = admin.services.publisher.disconnect(&id);
+    let result = found(rpc || events, &id);
+    admin.audit(actor, "disconnect", id, result).await
+}
+
+async fn pause_plugin(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = found(admin.services.publisher.set_paused(&id, true), &id);
+    admin.audit(actor, "pause", id, result).await
+}
+
+async fn resume_plugin(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = found(admin.services.publisher.set_paused(&id, false), &id);
+    admin.audit(actor, "resume", id, result).await
+}
+
+async fn set_event_filter(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+    Json(filter): Json<EventFilter>,
+) -> Response {
+    let applied = admin
+        .services
+        .publisher
+        .set_muted(&id, filter.event_type, filter.muted);
+    let result = found(applied, &id).map(|_| {
+        let state = if filter.muted { "muted" } else { "unmuted" };
+        Some(format!("{state} {:?}", filter.event_type))
+    });
+    admin.audit(actor, "set_event_filter", id, result).await
+}
+
+async fn sync_commands(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = match admin.services.resync_commands(&id).await {
+        Ok(count) => Ok(Some(format!("{count} commands synced"))),
+        Err(why) => Err((StatusCode::BAD_GATEWAY, why.to_string())),
+    };
+    admin.audit(actor, "sync_commands", id, result).await
+}
+
+async fn send_test_message(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(channel_id): Path<ChannelId>,
+    Json(message): Json<TestMessage>,
+) -> Response {
+    let content = message
+        .content
+        .unwrap_or_else(|| DEFAULT_TEST_MESSAGE.to_owned());
+    let result = match channel_id
+        .send_message(
+            &admin.services.discord_http,
+            CreateMessage::new().content(content),
+        )
+        .await
+    {
+        Ok(message) => Ok(Some(format!("sent message {}", message.id))),
+        Err(why) => Err((StatusCode::BAD_GATEWAY, why.to_string())),
+    };
+    admin
+        .audit(actor, "send_test_message", channel_id.to_string(), result)
+        .await
+}
+
+async fn supervised_plugin_logs(
+    State(admin): State<Admin>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<LogLine>>, StatusCode> {
+    admin
+        .supervisor
+        .logs(&id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn enable_plugin(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = supervised(admin.supervisor.set_enabled(&id, true), &id);
+    admin.audit(actor, "enable", id, result).await
+}
+
+async fn disable_plugin(
+    State(admin): State<Admin>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = supervised(admin.supervisor.set_enabled(&id, false), &id);
+    admin.audit(actor, "disable", id, result).await
+}
+
+fn found(found: bool, id: &str) -> Result<Option<String>, (StatusCode, String)> {
+    match found {
+        true => Ok(None),
+        false => Err((
+            StatusCode::NOT_FOUND,
+            format!("plugin '{id}' is not connected"),
+        )),
+    }
+}
+
+// Supervised plugins are known whether they're running or not
+fn supervised(found: bool, id: &str) -> Result<Option<String>, (StatusCode, String)> {
+    match found {
+        true => Ok(None),
+        false => Err((StatusCode::NOT_FOUND, format!("unknown plugin '{id}'"))),
+    }
+}
+
+async fn append_line(path: &std::path::Path, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await
+}
+
+// Doesn't bail out on the first mismatching byte, so timing doesn't leak how much of a token
+// was guessed right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
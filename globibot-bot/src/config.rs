@@ -0,0 +1,773 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use globibot_core::{
+    events::EventType,
+    rpc::{self, PresenceStatus},
+    serenity::all::GatewayIntents,
+    transport::Address,
+};
+use serde::{Deserialize, Deserializer, de};
+use toml::{Table, Value};
+
+use crate::storage;
+
+pub const DEFAULT_PATH: &str = "globibot.toml";
+const MIN_ADMIN_TOKEN_LEN: usize = 16;
+// Discord rate limits presence updates
+pub const MIN_PRESENCE_ROTATION_SECS: u64 = 15;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub listeners: Listeners,
+    pub discord: Discord,
+    #[serde(default)]
+    pub web: Web,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub events: Events,
+    #[serde(default)]
+    pub supervisor: Supervisor,
+    #[serde(default)]
+    pub telemetry: Telemetry,
+    #[serde(default)]
+    pub shutdown: Shutdown,
+    #[serde(default)]
+    pub plugins: HashMap<String, PluginConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listeners {
+    pub subscriber_addr: Address,
+    pub rpc_addr: Address,
+    #[serde(default, deserialize_with = "deserialize_socket_mode")]
+    pub socket_mode: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Discord {
+    pub token: Secret,
+    pub application_id: u64,
+    #[serde(default = "default_intents", deserialize_with = "deserialize_intents")]
+    pub intents: GatewayIntents,
+    #[serde(default)]
+    pub shards: Sharding,
+    #[serde(default)]
+    pub presence: Presence,
+}
+
+// Gateway shards run by this process, ranges let several processes split the bot's guilds
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Sharding {
+    // As many shards as Discord recommends
+    Auto,
+    Fixed { total: u32 },
+    // Shards `first..=last` out of `total`
+    Range { first: u32, last: u32, total: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Presence {
+    #[serde(default)]
+    pub status: PresenceStatus,
+    // Rotated through, a single activity stays up
+    #[serde(default)]
+    pub activities: Vec<rpc::Activity>,
+    #[serde(default = "default_presence_rotation_secs")]
+    pub rotation_interval_secs: u64,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Self {
+            status: PresenceStatus::default(),
+            activities: Vec::new(),
+            rotation_interval_secs: default_presence_rotation_secs(),
+        }
+    }
+}
+
+impl Default for Sharding {
+    fn default() -> Self {
+        Self::Fixed { total: 1 }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Web {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_web_addr")]
+    pub addr: String,
+    #[serde(default)]
+    pub activity: Activity,
+    #[serde(default)]
+    pub admin: Admin,
+}
+
+// Live log of events and RPC calls, off by default since it keeps recent traffic in memory
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Activity {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_activity_history")]
+    pub history: usize,
+    #[serde(default = "default_true")]
+    pub redact_content: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Storage {
+    #[serde(default = "default_storage_path")]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Events {
+    pub record_path: Option<PathBuf>,
+    #[serde(default)]
+    pub queue: EventQueue,
+}
+
+// Retention of the queues of plugins with `durable_events`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventQueue {
+    #[serde(default = "default_queue_retention_secs")]
+    pub retention_secs: u64,
+    #[serde(default = "default_queue_max_events")]
+    pub max_events: u64,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self {
+            retention_secs: default_queue_retention_secs(),
+            max_events: default_queue_max_events(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Shutdown {
+    // How long plugins get to receive the shutdown event and finish their RPC calls
+    #[serde(default = "default_drain_timeout_secs")]
+    pub drain_timeout_secs: u64,
+    // Events kept for plugins resuming with a restart token
+    #[serde(default = "default_replay_window_secs")]
+    pub replay_window_secs: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: default_drain_timeout_secs(),
+            replay_window_secs: default_replay_window_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Supervisor {
+    #[serde(default)]
+    pub enabled: bool,
+    pub subscriber_addr: Option<Address>,
+    pub rpc_addr: Option<Address>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Telemetry {
+    // Full OTLP/HTTP traces URL, e.g. `http://localhost:4318/v1/traces`
+    pub otlp_endpoint: Option<String>,
+}
+
+// Plugin sections are shared with the plugins' own settings, unknown keys are theirs
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginConfig {
+    #[serde(flatten)]
+    pub policy: PluginPolicy,
+    pub command: Option<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub wasm: Option<WasmPlugin>,
+    // The whole section, handed over to supervised plugins
+    #[serde(skip)]
+    pub settings: Table,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub struct WasmPlugin {
+    pub path: PathBuf,
+    // Fuel granted to each event handler invocation
+    #[serde(default = "default_wasm_fuel")]
+    pub fuel: u64,
+    #[serde(default = "default_wasm_max_memory")]
+    pub max_memory: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginPolicy {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_true")]
+    pub rpc: bool,
+    pub events: Option<HashSet<EventType>>,
+    // Queue events on disk while the plugin is away, until it acknowledges them
+    #[serde(default)]
+    pub durable_events: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PluginPolicies {
+    policies: Arc<HashMap<String, PluginPolicy>>,
+    // Toggled by admins at runtime, takes precedence over `enabled` from the config
+    enabled: Arc<parking_lot::RwLock<HashMap<String, bool>>>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Secret(String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file '{path}': {err}", path = .0.display(), err = .1)]
+    Read(PathBuf, io::Error),
+
+    #[error("Invalid config: {0}")]
+    Invalid(#[from] toml::de::Error),
+
+    #[error("Invalid config: {0}")]
+    Validation(String),
+}
+
+const ENV_OVERRIDES: &[(&str, &[&str], EnvKind)] = &[
+    (
+        "SUBSCRIBER_ADDR",
+        &["listeners", "subscriber_addr"],
+        EnvKind::String,
+    ),
+    ("RPC_ADDR", &["listeners", "rpc_addr"], EnvKind::String),
+    (
+        "SOCKET_MODE",
+        &["listeners", "socket_mode"],
+        EnvKind::String,
+    ),
+    ("DISCORD_TOKEN", &["discord", "token"], EnvKind::String),
+    (
+        "APPLICATION_ID",
+        &["discord", "application_id"],
+        EnvKind::Integer,
+    ),
+    ("DISCORD_INTENTS", &["discord", "intents"], EnvKind::List),
+    (
+        "DISCORD_SHARD_MODE",
+        &["discord", "shards", "mode"],
+        EnvKind::String,
+    ),
+    (
+        "DISCORD_SHARD_TOTAL",
+        &["discord", "shards", "total"],
+        EnvKind::Integer,
+    ),
+    (
+        "DISCORD_SHARD_FIRST",
+        &["discord", "shards", "first"],
+        EnvKind::Integer,
+    ),
+    (
+        "DISCORD_SHARD_LAST",
+        &["discord", "shards", "last"],
+        EnvKind::Integer,
+    ),
+    (
+        "DISCORD_PRESENCE_STATUS",
+        &["discord", "presence", "status"],
+        EnvKind::String,
+    ),
+    ("WEB_ENABLED", &["web", "enabled"], EnvKind::Boolean),
+    ("WEB_ADDR", &["web", "addr"], EnvKind::String),
+    (
+        "WEB_ADMIN_TOKEN",
+        &["web", "admin", "tokens", "admin"],
+        EnvKind::String,
+    ),
+    (
+        "WEB_AUDIT_PATH",
+        &["web", "admin", "audit_path"],
+        EnvKind::String,
+    ),
+    (
+        "WEB_ACTIVITY_ENABLED",
+        &["web", "activity", "enabled"],
+        EnvKind::Boolean,
+    ),
+    (
+        "SHUTDOWN_DRAIN_TIMEOUT_SECS",
+        &["shutdown", "drain_timeout_secs"],
+        EnvKind::Integer,
+    ),
+    (
+        "SUPERVISOR_ENABLED",
+        &["supervisor", "enabled"],
+        EnvKind::Boolean,
+    ),
+    ("STORAGE_PATH", &["storage", "path"], EnvKind::String),
+    (
+        "OTLP_ENDPOINT",
+        &["telemetry", "otlp_endpoint"],
+        EnvKind::String,
+    ),
+    (
+        "EVENTS_RECORD_PATH",
+        &["events", "record_path"],
+        EnvKind::String,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum EnvKind {
+    String,
+    Integer,
+    Boolean,
+    List,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let source = match path {
+            Some(path) => Some(path.to_owned()),
+            None => Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists()),
+        };
+        let mut table = match source {
+            Some(path) => read_table(&path)?,
+            None => Table::new(),
+        };
+        apply_env_overrides(&mut table, |var| env::var(var).ok())?;
+
+        Self::from_table(table)
+    }
+
+    fn from_table(table: Table) -> Result<Self, ConfigError> {
+        let sections = table
+            .get("plugins")
+            .and_then(Value::as_table)
+            .cloned()
+            .unwrap_or_default();
+        let mut config: Config = Value::Table(table).try_into()?;
+        for (id, plugin) in &mut config.plugins {
+            if let Some(section) = sections.get(id).and_then(Value::as_table) {
+                plugin.settings = section.clone();
+            }
+        }
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.listeners.subscriber_addr == self.listeners.rpc_addr {
+            return Err(ConfigError::Validation(
+                "listeners.subscriber_addr and listeners.rpc_addr must differ".to_owned(),
+            ));
+        }
+        if self.discord.token.expose().is_empty() {
+            return Err(ConfigError::Validation("discord.token is empty".to_owned()));
+        }
+        match self.discord.shards {
+            Sharding::Fixed { total: 0 } | Sharding::Range { total: 0, .. } => {
+                return Err(ConfigError::Validation(
+                    "discord.shards.total must be at least 1".to_owned(),
+                ));
+            }
+            Sharding::Range { first, last, total } if first > last || last >= total => {
+                return Err(ConfigError::Validation(format!(
+                    "discord.shards range {first}..={last} doesn't fit in {total} shards"
+                )));
+            }
+            _ => {}
+        }
+        if self.discord.presence.rotation_interval_secs < MIN_PRESENCE_ROTATION_SECS {
+            return Err(ConfigError::Validation(format!(
+                "discord.presence.rotation_interval_secs must be at least {MIN_PRESENCE_ROTATION_SECS}"
+            )));
+        }
+        if let Some(id) = self.plugins.keys().find(|id| storage::is_reserved_id(id)) {
+            return Err(ConfigError::Validation(format!(
+                "plugin ID '{id}' is reserved, it can't start with '@'"
+            )));
+        }
+        if let Some((id, _)) = self
+            .plugins
+            .iter()
+            .find(|(_, plugin)| plugin.command.is_some() && plugin.wasm.is_some())
+        {
+            return Err(ConfigError::Validation(format!(
+                "plugin '{id}' can't have both a command and a wasm component"
+            )));
+        }
+        if let Some((name, _)) = self
+            .web
+            .admin
+            .tokens
+            .iter()
+            .find(|(_, token)| token.expose().len() < MIN_ADMIN_TOKEN_LEN)
+        {
+            return Err(ConfigError::Validation(format!(
+                "admin token '{name}' should be at least {MIN_ADMIN_TOKEN_LEN} characters long"
+            )));
+        }
+        if self.supervisor.enabled && !self.plugins.values().any(|p| p.command.is_some()) {
+            return Err(ConfigError::Validation(
+                "supervisor is enabled but no plugin has a command".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn policies(&self) -> PluginPolicies {
+        let policies = self
+            .plugins
+            .iter()
+            .map(|(id, plugin)| (id.clone(), plugin.policy.clone()))
+            .collect();
+        PluginPolicies {
+            policies: Arc::new(policies),
+            enabled: <_>::default(),
+        }
+    }
+}
+
+impl PluginPolicy {
+    pub fn allowed_events(&self, requested: HashSet<EventType>) -> HashSet<EventType> {
+        match &self.events {
+            Some(allowed) => requested.intersection(allowed).copied().collect(),
+            None => requested,
+        }
+    }
+}
+
+impl Default for PluginPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rpc: true,
+            events: None,
+            durable_events: false,
+        }
+    }
+}
+
+impl PluginPolicies {
+    pub fn get(&self, plugin_id: &str) -> PluginPolicy {
+        let mut policy = self.policies.get(plugin_id).cloned().unwrap_or_default();
+        if let Some(enabled) = self.enabled.read().get(plugin_id) {
+            policy.enabled = *enabled;
+        }
+        policy
+    }
+
+    pub fn set_enabled(&self, plugin_id: &str, enabled: bool) {
+        self.enabled.write().insert(plugin_id.to_owned(), enabled);
+    }
+}
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"<redacted>\"")
+    }
+}
+
+impl Default for Web {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            addr: default_web_addr(),
+            activity: Activity::default(),
+            admin: Admin::default(),
+        }
+    }
+}
+
+// Admin endpoints are only served when at least one token is configured
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Admin {
+    // Bearer tokens, keyed by the name admin actions are audited under
+    #[serde(default)]
+    pub tokens: HashMap<String, Secret>,
+    pub audit_path: Option<PathBuf>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            history: default_activity_history(),
+            redact_content: true,
+        }
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            path: default_storage_path(),
+        }
+    }
+}
+
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let content =
+        fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+    Ok(toml::from_str(&content)?)
+}
+
+fn apply_env_overrides(
+    table: &mut Table,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<(), ConfigError> {
+    for &(var, keys, kind) in ENV_OVERRIDES {
+        if let Some(raw) = lookup(var) {
+            let value = env_value(var, &raw, kind)?;
+            insert_at(table, keys, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn env_value(var: &str, raw: &str, kind: EnvKind) -> Result<Value, ConfigError> {
+    let malformed = |expected| {
+        ConfigError::Validation(format!(
+            "environment variable '{var}' should be {expected}, got '{raw}'"
+        ))
+    };
+
+    Ok(match kind {
+        EnvKind::String => Value::String(raw.to_owned()),
+        EnvKind::Integer => Value::Integer(raw.parse().map_err(|_| malformed("an integer"))?),
+        EnvKind::Boolean => Value::Boolean(raw.parse().map_err(|_| malformed("a boolean"))?),
+        EnvKind::List => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_owned()))
+                .collect(),
+        ),
+    })
+}
+
+fn insert_at(table: &mut Table, keys: &[&str], value: Value) {
+    let (last, parents) = keys
+        .split_last()
+        .expect("Override keys should not be empty");
+
+    let mut table = table;
+    for key in parents {
+        let entry = table
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        table = entry.as_table_mut().expect("Entry was just made a table");
+    }
+
+    table.insert(last.to_string(), value);
+}
+
+fn deserialize_intents<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<GatewayIntents, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+
+    names
+        .iter()
+        .try_fold(GatewayIntents::empty(), |intents, name| {
+            GatewayIntents::from_name(name)
+                .map(|intent| intents | intent)
+                .ok_or_else(|| de::Error::custom(format!("unknown gateway intent '{name}'")))
+        })
+}
+
+fn deserialize_socket_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    let mode = String::deserialize(deserializer)?;
+
+    u32::from_str_radix(&mode, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o777)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("invalid octal file mode '{mode}'")))
+}
+
+fn default_intents() -> GatewayIntents {
+    GatewayIntents::default().union(GatewayIntents::MESSAGE_CONTENT)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_web_addr() -> String {
+    "0.0.0.0:8001".to_owned()
+}
+
+fn default_activity_history() -> usize {
+    500
+}
+
+fn default_queue_retention_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_queue_max_events() -> u64 {
+    10_000
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    10
+}
+
+fn default_replay_window_secs() -> u64 {
+    30
+}
+
+fn default_presence_rotation_secs() -> u64 {
+    5 * 60
+}
+
+fn default_wasm_fuel() -> u64 {
+    100_000_000
+}
+
+fn default_wasm_max_memory() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_storage_path() -> PathBuf {
+    PathBuf::from("globibot.redb")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+        [listeners]
+        subscriber_addr = "tcp://127.0.0.1:4000"
+        rpc_addr = "tcp://127.0.0.1:4001"
+
+        [discord]
+        token = "token"
+        application_id = 1
+    "#;
+
+    fn table(extra: &str) -> Table {
+        toml::from_str(&format!("{MINIMAL}\n{extra}")).unwrap()
+    }
+
+    fn validation_error(extra: &str) -> String {
+        match Config::from_table(table(extra)) {
+            Err(ConfigError::Validation(why)) => why,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn env_overrides_take_precedence_over_the_file() {
+        let env = HashMap::from([
+            ("DISCORD_TOKEN", "from env"),
+            ("DISCORD_SHARD_MODE", "range"),
+            ("DISCORD_SHARD_TOTAL", "4"),
+            ("DISCORD_SHARD_FIRST", "2"),
+            ("DISCORD_SHARD_LAST", "3"),
+            ("DISCORD_INTENTS", "GUILDS, DIRECT_MESSAGES"),
+            ("WEB_ENABLED", "false"),
+        ]);
+        let mut table = table("");
+        apply_env_overrides(&mut table, |var| env.get(var).map(|raw| raw.to_string())).unwrap();
+        let config = Config::from_table(table).unwrap();
+
+        assert_eq!(config.discord.token.expose(), "from env");
+        assert!(matches!(
+            config.discord.shards,
+            Sharding::Range {
+                first: 2,
+                last: 3,
+                total: 4
+            }
+        ));
+        assert_eq!(
+            config.discord.intents,
+            GatewayIntents::GUILDS | GatewayIntents::DIRECT_MESSAGES
+        );
+        assert!(!config.web.enabled);
+    }
+
+    #[test]
+    fn malformed_env_overrides_are_rejected() {
+        let mut table = table("");
+        let result = apply_env_overrides(&mut table, |var| {
+            (var == "DISCORD_SHARD_TOTAL").then(|| "four".to_owned())
+        });
+        assert!(matches!(result, Err(ConfigError::Validation(_))));
+    }
+
+    #[test]
+    fn shard_ranges_fit_in_the_total() {
+        let range = |first, last, total| {
+            format!(
+                "[discord.shards]\nmode = \"range\"\nfirst = {first}\nlast = {last}\ntotal = {total}"
+            )
+        };
+
+        assert!(Config::from_table(table(&range(0, 1, 2))).is_ok());
+        assert!(validation_error(&range(1, 0, 2)).contains("doesn't fit"));
+        assert!(validation_error(&range(0, 2, 2)).contains("doesn't fit"));
+        assert!(validation_error(&range(0, 0, 0)).contains("at least 1"));
+    }
+
+    #[test]
+    fn reserved_plugin_ids_are_rejected() {
+        assert!(validation_error("[plugins.\"@bot\"]").contains("reserved"));
+    }
+
+    #[test]
+    fn short_admin_tokens_are_rejected() {
+        let tokens = |token: &str| format!("[web.admin.tokens]\nalice = \"{token}\"");
+
+        assert!(Config::from_table(table(&tokens(&"a".repeat(MIN_ADMIN_TOKEN_LEN)))).is_ok());
+        assert!(
+            validation_error(&tokens(&"a".repeat(MIN_ADMIN_TOKEN_LEN - 1))).contains("at least")
+        );
+    }
+}
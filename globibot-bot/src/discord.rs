@@ -1,7 +1,19 @@
-use crate::events::Publisher;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    config::Sharding,
+    events::Publisher,
+    metrics,
+    presence::{self, PresenceControl},
+    web::{ShardStatus, WEB_STATE},
+};
 
 use globibot_core::events::Event;
-use globibot_core::serenity::all::GatewayIntents;
+use globibot_core::serenity::all::{
+    GatewayIntents, GuildChannel, GuildScheduledEventUserAddEvent,
+    GuildScheduledEventUserRemoveEvent, MessagePollVoteAddEvent, MessagePollVoteRemoveEvent,
+    PartialGuildChannel, RatelimitInfo, Ready, ShardManager, ShardStageUpdateEvent,
+};
 use globibot_core::serenity::{
     self, Client, async_trait,
     client::Context,
@@ -14,23 +26,35 @@ use globibot_core::serenity::{
 
 struct EventHandler {
     publisher: Publisher,
+    presence: PresenceControl,
 }
 
 #[async_trait]
 impl serenity::client::EventHandler for EventHandler {
-    async fn message(&self, _ctx: Context, new_message: Message) {
-        self.publisher.broadcast(Event::MessageCreate {
-            message: Box::new(new_message),
-        });
+    async fn message(&self, ctx: Context, new_message: Message) {
+        let message = Box::new(new_message);
+        let event = match message.guild_id {
+            Some(_) => Event::MessageCreate { message },
+            None => Event::DirectMessageCreate { message },
+        };
+        self.publisher.broadcast_from_shard(ctx.shard_id.0, event);
     }
 
-    async fn interaction_create(&self, _ctx: Context, interaction: Interaction) {
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         let Some(command) = interaction.command() else {
             return;
         };
-        self.publisher.broadcast(Event::InteractionCreate {
-            interaction: Box::new(command),
-        });
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::InteractionCreate {
+                interaction: Box::new(command),
+            },
+        );
+    }
+
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        let (activity, status) = presence::gateway_presence(&self.presence.current());
+        ctx.set_presence(activity, status);
     }
 
     async fn cache_ready(&self, _ctx: Context, _guilds: Vec<GuildId>) {
@@ -39,28 +63,138 @@ impl serenity::client::EventHandler for EventHandler {
 
     async fn message_delete(
         &self,
-        _ctx: Context,
+        ctx: Context,
         channel_id: ChannelId,
         message_id: MessageId,
         _gid: Option<GuildId>,
     ) {
-        self.publisher.broadcast(Event::MessageDelete {
-            channel_id,
-            message_id,
-        });
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::MessageDelete {
+                channel_id,
+                message_id,
+            },
+        );
+    }
+
+    async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::ThreadCreate {
+                thread: Box::new(thread),
+            },
+        );
+    }
+
+    async fn thread_update(&self, ctx: Context, _old: Option<GuildChannel>, new: GuildChannel) {
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::ThreadUpdate {
+                thread: Box::new(new),
+            },
+        );
+    }
+
+    async fn thread_delete(
+        &self,
+        ctx: Context,
+        thread: PartialGuildChannel,
+        _full_thread_data: Option<GuildChannel>,
+    ) {
+        self.publisher
+            .broadcast_from_shard(ctx.shard_id.0, Event::ThreadDelete { thread });
+    }
+
+    async fn poll_vote_add(&self, ctx: Context, vote: MessagePollVoteAddEvent) {
+        self.publisher
+            .broadcast_from_shard(ctx.shard_id.0, Event::PollVoteAdd { vote });
+    }
+
+    async fn poll_vote_remove(&self, ctx: Context, vote: MessagePollVoteRemoveEvent) {
+        self.publisher
+            .broadcast_from_shard(ctx.shard_id.0, Event::PollVoteRemove { vote });
+    }
+
+    async fn guild_scheduled_event_user_add(
+        &self,
+        ctx: Context,
+        subscription: GuildScheduledEventUserAddEvent,
+    ) {
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::ScheduledEventUserAdd { subscription },
+        );
+    }
+
+    async fn guild_scheduled_event_user_remove(
+        &self,
+        ctx: Context,
+        subscription: GuildScheduledEventUserRemoveEvent,
+    ) {
+        self.publisher.broadcast_from_shard(
+            ctx.shard_id.0,
+            Event::ScheduledEventUserRemove { subscription },
+        );
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        tracing::info!("Shard {} is {}", event.shard_id, event.new);
+        WEB_STATE
+            .lock()
+            .unwrap()
+            .update_shard_stage(event.shard_id.0, event.new.to_string());
+    }
+
+    async fn ratelimit(&self, data: RatelimitInfo) {
+        metrics::DISCORD_RATELIMITS
+            .with_label_values(&[data.global.to_string()])
+            .inc();
     }
 }
 
 pub async fn client(
     token: &str,
     publisher: Publisher,
+    presence: PresenceControl,
     application_id: u64,
+    intents: GatewayIntents,
 ) -> serenity::Result<Client> {
-    Client::builder(
-        token,
-        GatewayIntents::default().union(GatewayIntents::MESSAGE_CONTENT),
-    )
-    .event_handler(EventHandler { publisher })
-    .application_id(application_id.into())
-    .await
+    Client::builder(token, intents)
+        .event_handler(EventHandler {
+            publisher,
+            presence,
+        })
+        .application_id(application_id.into())
+        .await
+}
+
+pub async fn start(mut client: Client, sharding: Sharding) -> serenity::Result<()> {
+    match sharding {
+        Sharding::Auto => client.start_autosharded().await,
+        Sharding::Fixed { total } => client.start_shards(total).await,
+        // Serenity treats the end of the range as inclusive
+        Sharding::Range { first, last, total } => {
+            client.start_shard_range(first..last, total).await
+        }
+    }
+}
+
+pub async fn run_shard_monitor(shard_manager: Arc<ShardManager>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10));
+
+    loop {
+        interval.tick().await;
+        for (shard_id, runner) in shard_manager.runners.lock().await.iter() {
+            if let Some(latency) = runner.latency {
+                metrics::GATEWAY_LATENCY
+                    .with_label_values(&[shard_id.to_string()])
+                    .set(latency.as_secs_f64());
+            }
+            WEB_STATE.lock().unwrap().update_shard(ShardStatus {
+                id: shard_id.0,
+                stage: runner.stage.to_string(),
+                latency: runner.latency,
+            });
+        }
+    }
 }
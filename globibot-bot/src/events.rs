@@ -1,41 +1,95 @@
 use futures::{Sink, SinkExt, Stream, StreamExt};
-use globibot_core::events::{AcceptError, Event, EventType, accept};
-use std::{collections::HashSet, fmt::Display, io, time::Duration};
+use globibot_core::{
+    events::{
+        AcceptError, Balance, ConsumerGroup, Event, EventAck, EventType, HandshakeRequest,
+        RecordedEvent, TracedEvent, accept,
+    },
+    trace::{self, TraceContext},
+    transport::PeerAddr,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher},
+    io,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
-    sync::broadcast,
+    fs::OpenOptions,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{
+        Notify,
+        broadcast::{
+            self,
+            error::{RecvError, TryRecvError},
+        },
+        mpsc, oneshot,
+    },
     time::timeout,
 };
-use tracing::{debug, info, warn};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, info, info_span, warn};
+
+use crate::{
+    activity,
+    config::PluginPolicies,
+    metrics,
+    queue::{EventQueue, Interest},
+    storage::{self, QueuedEvent},
+    web::{self, EventsEndpoint, WEB_STATE},
+};
 
-use crate::web::WEB_STATE;
+pub trait EventSink = Sink<TracedEvent, Error: Display> + Send + Unpin + 'static;
+// Plugins with durable events acknowledge them over the same connection
+pub trait AckedEventSink = EventSink + Stream<Item = io::Result<EventAck>>;
 
-pub trait EventSink = Sink<Event, Error: Display> + Send + Unpin + 'static;
+const QUEUE_BATCH_SIZE: usize = 64;
+const QUEUE_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Subscribers not taking an event in time are dropped rather than holding up the others
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// Longer than a single send to a subscriber may take
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub async fn run_publisher<S, T>(transports: S, publisher: Publisher) -> io::Result<()>
+pub async fn run_publisher<S, T>(
+    transports: S,
+    publisher: Publisher,
+    policies: PluginPolicies,
+    queue: EventQueue,
+    shutdown: CancellationToken,
+) -> io::Result<()>
 where
     S: Stream<Item = io::Result<T>>,
-    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    T: AsyncRead + AsyncWrite + PeerAddr + Send + Unpin + 'static,
 {
     let mut transports = std::pin::pin!(transports);
 
-    while let Some(transport) = transports.next().await.transpose()? {
+    while let Some(transport) = shutdown
+        .run_until_cancelled(transports.next())
+        .await
+        .flatten()
+        .transpose()?
+    {
         debug!("About to accept new subscriber");
+        let remote_addr = transport.peer_addr();
         match accept(transport).await {
+            Ok((request, subscriber)) if policies.get(&request.id).durable_events => {
+                spawn_durable_subscriber(
+                    &publisher,
+                    &policies,
+                    &queue,
+                    request,
+                    remote_addr,
+                    subscriber,
+                );
+            }
             Ok((request, subscriber)) => {
-                let subscriber = publisher.add_subscriber(subscriber, request.events);
-                tokio::spawn({
-                    let plugin_id = request.id.clone();
-                    async move {
-                        subscriber.run().await;
-                        WEB_STATE.lock().unwrap().remove_plugin(&plugin_id);
-                    }
-                });
-                WEB_STATE
-                    .lock()
-                    .unwrap()
-                    .register_plugin_events(&request.id);
-                info!("New event subscriber spawned: '{id}'", id = request.id);
+                spawn_subscriber(&publisher, &policies, request, remote_addr, subscriber);
             }
             Err(AcceptError::IO(err)) => {
                 warn!("IO error while accepting new subscriber: {}", err);
@@ -46,78 +100,915 @@ where
             Err(AcceptError::HandshakeTimedOut) => {
                 warn!("Subscriber did not send a subscription request in time");
             }
+            Err(err @ AcceptError::IncompatibleProtocol { .. }) => {
+                warn!("Rejected subscriber: {err}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Cancelling the returned token ends the subscriber, `None` when it was rejected
+pub fn spawn_subscriber<T: EventSink>(
+    publisher: &Publisher,
+    policies: &PluginPolicies,
+    request: HandshakeRequest,
+    remote_addr: Option<String>,
+    sink: T,
+) -> Option<CancellationToken> {
+    let subscription = subscribe(policies, &request, remote_addr)?;
+    let disconnect = subscription.disconnect.clone();
+    let subscriber = publisher.add_subscriber(
+        request.id.clone(),
+        sink,
+        subscription,
+        request.restart_token.as_deref(),
+    );
+    if !subscriber.backlog.is_empty() {
+        info!(
+            "Replaying {count} events to resuming subscriber '{id}'",
+            count = subscriber.backlog.len(),
+            id = request.id
+        );
+    }
+    let generation = subscriber.generation;
+    publisher.spawn_subscriber_task(request.id.clone(), generation, subscriber.run());
+    info!("New event subscriber spawned: '{id}'", id = request.id);
+    Some(disconnect)
+}
+
+// Events come from the plugin's queue rather than the broadcast channel, so that they survive
+// the plugin being away
+fn spawn_durable_subscriber<T: AckedEventSink>(
+    publisher: &Publisher,
+    policies: &PluginPolicies,
+    queue: &EventQueue,
+    request: HandshakeRequest,
+    remote_addr: Option<String>,
+    transport: T,
+) {
+    // Every instance would read the same queue, the group couldn't split it
+    if request.group.is_some() {
+        warn!(
+            "Rejected durable subscriber '{id}': consumer groups aren't supported with durable events",
+            id = request.id
+        );
+        return;
+    }
+    let Some(subscription) = subscribe(policies, &request, remote_addr) else {
+        return;
+    };
+    let interest = Interest {
+        events: subscription.events.clone(),
+        topics: subscription.topics.clone(),
+    };
+    // Missed events are already queued, the restart token has nothing to add
+    let subscriber = publisher.add_subscriber(request.id.clone(), transport, subscription, None);
+    let generation = subscriber.generation;
+    let queue = queue.clone();
+    publisher.spawn_subscriber_task(request.id.clone(), generation, async move {
+        match queue.register(&subscriber.plugin_id, interest).await {
+            Ok(notify) => subscriber.run_durable(queue, notify).await,
+            Err(why) => warn!(
+                "Failed to register the event queue of '{id}': {why}",
+                id = subscriber.plugin_id
+            ),
+        }
+    });
+    info!(
+        "New durable event subscriber spawned: '{id}'",
+        id = request.id
+    );
+}
+
+fn subscribe(
+    policies: &PluginPolicies,
+    request: &HandshakeRequest,
+    remote_addr: Option<String>,
+) -> Option<Subscription> {
+    if storage::is_reserved_id(&request.id) {
+        warn!(
+            "Rejected subscriber '{id}': plugin ID is reserved",
+            id = request.id
+        );
+        return None;
+    }
+    let policy = policies.get(&request.id);
+    if !policy.enabled {
+        warn!(
+            "Rejected subscriber '{id}': plugin is disabled",
+            id = request.id
+        );
+        return None;
+    }
+
+    let events = policy.allowed_events(request.events.clone());
+    let denied_events = request
+        .events
+        .difference(&events)
+        .copied()
+        .collect::<HashSet<_>>();
+    if !denied_events.is_empty() {
+        warn!(
+            "Denied events {denied_events:?} to subscriber '{id}'",
+            id = request.id
+        );
+    }
+
+    let generation = web::next_instance();
+    WEB_STATE.lock().unwrap().register_plugin_events(
+        &request.id,
+        request.protocol_version,
+        EventsEndpoint {
+            instance: generation,
+            group: request.group.clone(),
+            connected_at: SystemTime::now(),
+            remote_addr,
+            events: events.clone(),
+            topics: request.topics.clone(),
+            denied_events,
+            muted_events: HashSet::new(),
+            paused: false,
+        },
+    );
+
+    Some(Subscription {
+        generation,
+        group: request.group.clone(),
+        events,
+        topics: request.topics.clone(),
+        muted: <_>::default(),
+        paused: <_>::default(),
+        disconnect: <_>::default(),
+    })
+}
+
+pub async fn run_recorder(path: impl AsRef<Path>, publisher: Publisher) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let mut writer = BufWriter::new(file);
+    let mut receiver = publisher.sender.subscribe();
+
+    loop {
+        match receiver.recv().await {
+            Ok(BroadcastMessage { event, .. }) => {
+                let record = RecordedEvent {
+                    at: SystemTime::now(),
+                    event,
+                };
+                let mut line = serde_json::to_vec(&record)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+                writer.flush().await?;
+            }
+            Err(RecvError::Lagged(count)) => {
+                warn!("Event recorder lagged behind, {count} events were not recorded");
+            }
+            Err(RecvError::Closed) => break,
         }
     }
 
     Ok(())
 }
 
+// Fills the queues of plugins with durable events, whether they are connected or not
+pub async fn run_queue(queue: EventQueue, QueueFeed(mut feed): QueueFeed) {
+    let mut purge_interval = tokio::time::interval(QUEUE_PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            message = feed.recv() => match message {
+                Some(BroadcastMessage { event, trace, shard, target, delivery, .. }) => {
+                    let event = TracedEvent { event, trace, shard, ack_id: None };
+                    if queue.enqueue(&event, target.as_deref()).await > 0 {
+                        confirm(delivery.as_ref());
+                    }
+                }
+                None => return,
+            },
+            _ = purge_interval.tick() => {
+                if let Err(why) = queue.purge().await {
+                    warn!("Failed to purge expired queued events: {why}");
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct BroadcastMessage {
     event: Event,
+    trace: Option<TraceContext>,
+    shard: Option<u32>,
+    target: Option<String>,
+    // Instance picked to handle the event in each consumer group, by plugin and group ID
+    assignments: Arc<HashMap<String, HashMap<String, u64>>>,
+    delivery: Option<Delivery>,
+}
+
+// Every published message on its way to the durable queues. Unlike the broadcast channel it never
+// drops messages, a slow disk only delays them
+pub struct QueueFeed(mpsc::UnboundedReceiver<BroadcastMessage>);
+
+// Fired once a targeted event was sent to its plugin, or queued for it
+type Delivery = Arc<parking_lot::Mutex<Option<oneshot::Sender<()>>>>;
+
+fn confirm(delivery: Option<&Delivery>) {
+    if let Some(sender) = delivery.and_then(|delivery| delivery.lock().take()) {
+        sender.send(()).ok();
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Publisher {
     sender: broadcast::Sender<BroadcastMessage>,
+    queue_feed: mpsc::UnboundedSender<BroadcastMessage>,
+    // Every instance of each plugin, a reconnecting plugin shows up twice until the old
+    // connection is noticed as closed
+    subscriptions: Arc<parking_lot::Mutex<HashMap<String, Vec<Arc<Subscription>>>>>,
+    // Round-robin position of each consumer group
+    group_cursors: Arc<parking_lot::Mutex<HashMap<(String, String), usize>>>,
+    replay: Arc<parking_lot::Mutex<Replay>>,
+    tasks: TaskTracker,
+}
+
+// Recent events, for plugins coming back after a restart of the bot
+#[derive(Debug)]
+struct Replay {
+    window: Duration,
+    messages: VecDeque<(Instant, BroadcastMessage)>,
+    // Issued by the previous bot process, each plugin can resume with it once
+    restart_token: Option<String>,
+    resumed: HashSet<String>,
+}
+
+const REPLAY_CAPACITY: usize = 1 << 12;
+
+#[derive(Debug)]
+struct Subscription {
+    generation: u64,
+    group: Option<ConsumerGroup>,
+    events: HashSet<EventType>,
+    topics: HashSet<String>,
+    // Server-side filters toggled by admins, on top of what the plugin asked for
+    muted: parking_lot::RwLock<HashSet<EventType>>,
+    paused: AtomicBool,
+    disconnect: CancellationToken,
+}
+
+impl Subscription {
+    fn wants(&self, event: &Event) -> bool {
+        match event {
+            Event::Topic { topic, .. } => self.topics.contains(topic),
+            Event::Shutdown { .. } => true,
+            event => self.accepts(event.ty()),
+        }
+    }
+
+    fn accepts(&self, ty: EventType) -> bool {
+        self.events.contains(&ty) && !self.muted.read().contains(&ty)
+    }
 }
 
 #[derive(Debug)]
 struct Subscriber<Transport> {
+    plugin_id: String,
+    generation: u64,
     transport: Transport,
-    events: HashSet<EventType>,
+    subscription: Arc<Subscription>,
     receiver: broadcast::Receiver<BroadcastMessage>,
+    backlog: VecDeque<BroadcastMessage>,
 }
 
 impl<Transport: EventSink> Subscriber<Transport> {
     async fn run(mut self) {
-        while let Ok(BroadcastMessage { event }) = self.receiver.recv().await {
-            if !self.events.contains(&event.ty()) {
+        let plugin_id = self.plugin_id.as_str();
+
+        loop {
+            let (next_message, replayed) = match self.backlog.pop_front() {
+                Some(message) => (Ok(message), true),
+                None => tokio::select! {
+                    message = self.receiver.recv() => (message, false),
+                    _ = self.subscription.disconnect.cancelled() => {
+                        info!("Disconnected subscriber '{plugin_id}'");
+                        return;
+                    }
+                },
+            };
+            let BroadcastMessage {
+                event,
+                trace,
+                shard,
+                target,
+                assignments,
+                delivery,
+            } = match next_message {
+                Ok(message) => message,
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Subscriber '{plugin_id}' lagged behind, {count} events were dropped");
+                    metrics::EVENTS_DROPPED
+                        .with_label_values(&[plugin_id, "lagged"])
+                        .inc_by(count);
+                    web::record_events_dropped(plugin_id, count);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if target.is_some_and(|target| target != plugin_id) {
+                continue;
+            }
+            if !self.subscription.wants(&event) {
                 continue;
             }
+            let is_shutdown = event.ty() == EventType::Shutdown;
+            // Only the first instance to resume gets the replay, so it handles the whole group's
+            if let Some(group) = &self.subscription.group
+                && !replayed
+                && !is_shutdown
+                && assignments
+                    .get(plugin_id)
+                    .and_then(|groups| groups.get(&group.id))
+                    != Some(&self.generation)
+            {
+                continue;
+            }
+            if self.subscription.paused.load(Ordering::Relaxed) && !is_shutdown {
+                metrics::EVENTS_DROPPED
+                    .with_label_values(&[plugin_id, "paused"])
+                    .inc();
+                web::record_events_dropped(plugin_id, 1);
+                continue;
+            }
+
+            let event = TracedEvent {
+                event,
+                trace,
+                shard,
+                ack_id: None,
+            };
+            if !deliver(&mut self.transport, plugin_id, event).await {
+                return;
+            }
+            confirm(delivery.as_ref());
+            if is_shutdown {
+                return;
+            }
+        }
+    }
+}
 
-            let send_task = timeout(Duration::from_secs(5), self.transport.send(event.clone()));
+impl<Transport: AckedEventSink> Subscriber<Transport> {
+    async fn run_durable(self, queue: EventQueue, notify: Arc<Notify>) {
+        let Self {
+            plugin_id,
+            transport,
+            subscription,
+            mut receiver,
+            ..
+        } = self;
+        let plugin_id = plugin_id.as_str();
+        let (mut sink, mut acks) = transport.split();
 
-            match send_task.await {
-                Ok(Ok(_)) => {}
-                Ok(Err(why)) => {
-                    warn!("Failed to send event to subscriber: {why}");
-                    return;
+        let read_acks = async {
+            while let Some(ack) = acks.next().await {
+                match ack {
+                    Ok(EventAck { ack_id }) => {
+                        if let Err(why) = queue.ack(plugin_id, ack_id).await {
+                            warn!("Failed to acknowledge event {ack_id} of '{plugin_id}': {why}");
+                        }
+                    }
+                    Err(why) => {
+                        warn!("Invalid acknowledgement from '{plugin_id}': {why}");
+                        return;
+                    }
                 }
-                Err(_timed_out) => {
-                    warn!("Timed out while sending event to subscriber");
+            }
+        };
+
+        let deliver_queued = async {
+            // Unacknowledged events stay queued and are redelivered on the next connection
+            let mut next_seq = 0;
+            loop {
+                if let Some(shutdown) = take_shutdown(&mut receiver) {
+                    deliver(&mut sink, plugin_id, shutdown).await;
                     return;
                 }
+
+                let paused = subscription.paused.load(Ordering::Relaxed);
+                let pending = match paused {
+                    true => vec![],
+                    false => match queue.pending(plugin_id, next_seq, QUEUE_BATCH_SIZE).await {
+                        Ok(pending) => pending,
+                        Err(why) => {
+                            warn!("Failed to read the event queue of '{plugin_id}': {why}");
+                            return;
+                        }
+                    },
+                };
+
+                if !pending.is_empty() {
+                    for (seq, QueuedEvent { event, .. }) in pending {
+                        next_seq = seq + 1;
+                        // Muted since it was queued
+                        if !subscription.wants(&event.event) {
+                            queue.ack(plugin_id, seq).await.ok();
+                            continue;
+                        }
+                        let event = TracedEvent {
+                            ack_id: Some(seq),
+                            ..event
+                        };
+                        if !deliver(&mut sink, plugin_id, event).await {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+
+                tokio::select! {
+                    _ = notify.notified(), if !paused => {}
+                    _ = tokio::time::sleep(PAUSED_POLL_INTERVAL), if paused => {}
+                    message = receiver.recv() => match message {
+                        Ok(BroadcastMessage { event: event @ Event::Shutdown { .. }, trace, .. }) => {
+                            let event = TracedEvent { event, trace, shard: None, ack_id: None };
+                            deliver(&mut sink, plugin_id, event).await;
+                            return;
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return,
+                    },
+                }
+            }
+        };
+
+        tokio::select! {
+            () = deliver_queued => {}
+            () = read_acks => info!("Durable subscriber '{plugin_id}' closed its connection"),
+            _ = subscription.disconnect.cancelled() => {
+                info!("Disconnected subscriber '{plugin_id}'");
             }
         }
     }
 }
 
-impl Publisher {
-    pub fn new() -> Self {
-        Self {
-            sender: broadcast::channel(16).0,
+// The broadcast channel only carries the shutdown event to durable subscribers
+fn take_shutdown(receiver: &mut broadcast::Receiver<BroadcastMessage>) -> Option<TracedEvent> {
+    loop {
+        match receiver.try_recv() {
+            Ok(BroadcastMessage {
+                event: event @ Event::Shutdown { .. },
+                trace,
+                ..
+            }) => {
+                return Some(TracedEvent {
+                    event,
+                    trace,
+                    shard: None,
+                    ack_id: None,
+                });
+            }
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
         }
     }
+}
 
-    fn add_subscriber<T: EventSink>(
+// Whether the subscriber is still worth sending events to
+async fn deliver<S>(sink: &mut S, plugin_id: &str, event: TracedEvent) -> bool
+where
+    S: Sink<TracedEvent, Error: Display> + Unpin,
+{
+    match timeout(SEND_TIMEOUT, sink.send(event)).await {
+        Ok(Ok(_)) => {
+            metrics::EVENTS_DELIVERED
+                .with_label_values(&[plugin_id])
+                .inc();
+            web::record_events_delivered(plugin_id);
+            true
+        }
+        Ok(Err(why)) => {
+            warn!("Failed to send event to subscriber: {why}");
+            metrics::EVENTS_DROPPED
+                .with_label_values(&[plugin_id, "send_error"])
+                .inc();
+            web::record_events_dropped(plugin_id, 1);
+            false
+        }
+        Err(_timed_out) => {
+            warn!("Timed out while sending event to subscriber");
+            metrics::EVENTS_DROPPED
+                .with_label_values(&[plugin_id, "timeout"])
+                .inc();
+            web::record_events_dropped(plugin_id, 1);
+            false
+        }
+    }
+}
+
+impl Publisher {
+    pub fn new(replay_window: Duration, restart_token: Option<String>) -> (Self, QueueFeed) {
+        let (queue_feed, feed) = mpsc::unbounded_channel();
+        let publisher = Self {
+            sender: broadcast::channel(1 << 8).0,
+            queue_feed,
+            subscriptions: <_>::default(),
+            group_cursors: <_>::default(),
+            replay: Arc::new(parking_lot::Mutex::new(Replay {
+                window: replay_window,
+                messages: VecDeque::new(),
+                restart_token,
+                resumed: HashSet::new(),
+            })),
+            tasks: TaskTracker::new(),
+        };
+        (publisher, QueueFeed(feed))
+    }
+
+    fn add_subscriber<T>(
         &self,
+        plugin_id: String,
         transport: T,
-        events: impl IntoIterator<Item = EventType>,
+        subscription: Subscription,
+        restart_token: Option<&str>,
     ) -> Subscriber<T> {
+        let generation = subscription.generation;
+        let subscription = Arc::new(subscription);
+        self.subscriptions
+            .lock()
+            .entry(plugin_id.clone())
+            .or_default()
+            .push(Arc::clone(&subscription));
+
+        // Subscribing while holding the replay lock so that no event is both replayed and received
+        let mut replay = self.replay.lock();
+        let backlog = match restart_token {
+            Some(token) => replay.resume(&plugin_id, token),
+            None => VecDeque::new(),
+        };
+        let receiver = self.sender.subscribe();
+        drop(replay);
+
         Subscriber {
+            plugin_id,
+            generation,
             transport,
-            events: events.into_iter().collect(),
-            receiver: self.sender.subscribe(),
+            subscription,
+            receiver,
+            backlog,
+        }
+    }
+
+    fn spawn_subscriber_task(
+        &self,
+        plugin_id: String,
+        generation: u64,
+        run: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let publisher = self.clone();
+        self.tasks.spawn(async move {
+            run.await;
+            publisher.remove_subscriber(&plugin_id, generation);
+            WEB_STATE
+                .lock()
+                .unwrap()
+                .remove_plugin_events(&plugin_id, generation);
+        });
+    }
+
+    // Notifies every subscriber, they disconnect once the event is delivered
+    pub async fn shutdown(&self, restart_token: String, timeout: Duration) -> bool {
+        self.broadcast(Event::Shutdown { restart_token });
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+
+    fn remove_subscriber(&self, plugin_id: &str, generation: u64) {
+        let mut subscriptions = self.subscriptions.lock();
+        if let Some(instances) = subscriptions.get_mut(plugin_id) {
+            instances.retain(|subscription| subscription.generation != generation);
+            if instances.is_empty() {
+                subscriptions.remove(plugin_id);
+            }
         }
     }
 
+    // Paused subscribers drop the events they are sent, so they don't count
+    pub fn is_subscribed(&self, plugin_id: &str, ty: EventType) -> bool {
+        self.subscriptions
+            .lock()
+            .get(plugin_id)
+            .into_iter()
+            .flatten()
+            .any(|subscription| {
+                !subscription.paused.load(Ordering::Relaxed) && subscription.accepts(ty)
+            })
+    }
+
+    pub fn disconnect(&self, plugin_id: &str) -> bool {
+        self.with_subscription(plugin_id, |subscription| {
+            subscription.disconnect.cancel();
+        })
+    }
+
+    pub fn set_paused(&self, plugin_id: &str, paused: bool) -> bool {
+        self.with_subscription(plugin_id, |subscription| {
+            subscription.paused.store(paused, Ordering::Relaxed);
+        })
+    }
+
+    pub fn set_muted(&self, plugin_id: &str, ty: EventType, muted: bool) -> bool {
+        self.with_subscription(plugin_id, |subscription| {
+            let mut muted_events = subscription.muted.write();
+            match muted {
+                true => muted_events.insert(ty),
+                false => muted_events.remove(&ty),
+            };
+        })
+    }
+
+    // Admin actions apply to every instance of the plugin
+    fn with_subscription(&self, plugin_id: &str, f: impl Fn(&Subscription)) -> bool {
+        let Some(instances) = self.subscriptions.lock().get(plugin_id).cloned() else {
+            return false;
+        };
+        instances.iter().for_each(|subscription| f(subscription));
+        let subscription = &instances[0];
+
+        WEB_STATE.lock().unwrap().update_event_filters(
+            plugin_id,
+            subscription.paused.load(Ordering::Relaxed),
+            subscription.muted.read().clone(),
+        );
+        true
+    }
+
+    // A consumer group counts once, as a single of its instances gets each message
+    pub fn topic_subscribers(&self, topic: &str) -> usize {
+        let subscriptions = self.subscriptions.lock();
+        let subscribers = subscriptions
+            .iter()
+            .flat_map(|(plugin_id, instances)| instances.iter().map(move |sub| (plugin_id, sub)))
+            .filter(|(_, subscription)| subscription.topics.contains(topic));
+
+        let mut groups = HashSet::new();
+        subscribers
+            .filter(|(plugin_id, subscription)| match &subscription.group {
+                Some(group) => groups.insert((plugin_id.as_str(), group.id.as_str())),
+                None => true,
+            })
+            .count()
+    }
+
     pub fn broadcast(&self, event: Event) {
+        self.broadcast_message(event, None);
+    }
+
+    pub fn broadcast_from_shard(&self, shard: u32, event: Event) {
+        self.broadcast_message(event, Some(shard));
+    }
+
+    fn broadcast_message(&self, event: Event, shard: Option<u32>) {
+        let ty = event.ty();
+        metrics::EVENTS_BROADCAST
+            .with_label_values(&[format!("{ty:?}")])
+            .inc();
+        activity::record_event(&event, None);
+
+        match self.publish(BroadcastMessage {
+            event,
+            trace: broadcast_trace(ty),
+            shard,
+            target: None,
+            assignments: <_>::default(),
+            delivery: None,
+        }) {
+            Some(count) => debug!("Broadcasted {ty:?} to {count} subscribers"),
+            None => warn!("Failed to broadcast event"),
+        }
+    }
+
+    pub fn send_to(&self, plugin_id: &str, event: Event) {
+        self.send_message_to(plugin_id, event, None);
+    }
+
+    // Whether the plugin got the event, either from one of its connections or through its queue
+    pub async fn deliver_to(&self, plugin_id: &str, event: Event) -> bool {
+        let (sender, delivered) = oneshot::channel();
+        let delivery = Arc::new(parking_lot::Mutex::new(Some(sender)));
+        self.send_message_to(plugin_id, event, Some(delivery));
+
+        matches!(timeout(DELIVERY_TIMEOUT, delivered).await, Ok(Ok(())))
+    }
+
+    fn send_message_to(&self, plugin_id: &str, event: Event, delivery: Option<Delivery>) {
         let ty = event.ty();
+        metrics::EVENTS_BROADCAST
+            .with_label_values(&[format!("{ty:?}")])
+            .inc();
+        activity::record_event(&event, Some(plugin_id));
+
+        let message = BroadcastMessage {
+            event,
+            trace: broadcast_trace(ty),
+            shard: None,
+            target: Some(plugin_id.to_owned()),
+            assignments: <_>::default(),
+            delivery,
+        };
+        match self.publish(message) {
+            Some(_) => debug!("Sent {ty:?} to '{plugin_id}'"),
+            None => warn!("Failed to send event to '{plugin_id}'"),
+        }
+    }
+
+    // Number of connected subscribers the message was sent to, `None` if not even the queues got it
+    fn publish(&self, mut message: BroadcastMessage) -> Option<usize> {
+        message.assignments = Arc::new(self.assign_groups(&message));
+        let mut replay = self.replay.lock();
+        replay.push(&message);
+        let queued = self.queue_feed.send(message.clone()).is_ok();
+        match self.sender.send(message) {
+            Ok(count) => Some(count),
+            Err(_) => queued.then_some(0),
+        }
+    }
+}
+
+impl Publisher {
+    // Picks the instance of each consumer group that gets the message, among the ones that would
+    // deliver it
+    fn assign_groups(&self, message: &BroadcastMessage) -> HashMap<String, HashMap<String, u64>> {
+        if message.event.ty() == EventType::Shutdown {
+            return HashMap::new();
+        }
+
+        let subscriptions = self.subscriptions.lock();
+        // Plugins may pick the same group ID, each still gets its own share of the stream
+        let mut groups = BTreeMap::<(&str, &str), (Balance, Vec<u64>)>::new();
+        for (plugin_id, instances) in subscriptions.iter() {
+            if message
+                .target
+                .as_ref()
+                .is_some_and(|target| target != plugin_id)
+            {
+                continue;
+            }
+            for subscription in instances {
+                let Some(group) = &subscription.group else {
+                    continue;
+                };
+                if subscription.paused.load(Ordering::Relaxed)
+                    || !subscription.wants(&message.event)
+                {
+                    continue;
+                }
+                let (_, members) = groups
+                    .entry((plugin_id.as_str(), group.id.as_str()))
+                    .or_insert_with(|| (group.balance, Vec::new()));
+                members.push(subscription.generation);
+            }
+        }
+
+        let mut cursors = self.group_cursors.lock();
+        let mut assignments = HashMap::<String, HashMap<String, u64>>::new();
+        for ((plugin_id, group_id), (balance, mut members)) in groups {
+            members.sort_unstable();
+            let index = match (balance, message.event.channel_id()) {
+                (Balance::ChannelHash, Some(channel_id)) => {
+                    BuildHasherDefault::<DefaultHasher>::default().hash_one(channel_id) as usize
+                }
+                _ => {
+                    let cursor = cursors
+                        .entry((plugin_id.to_owned(), group_id.to_owned()))
+                        .or_default();
+                    *cursor = cursor.wrapping_add(1);
+                    *cursor
+                }
+            };
+            assignments
+                .entry(plugin_id.to_owned())
+                .or_default()
+                .insert(group_id.to_owned(), members[index % members.len()]);
+        }
+        assignments
+    }
+}
+
+impl Replay {
+    fn push(&mut self, message: &BroadcastMessage) {
+        // Without a token from the previous process, no subscriber can resume
+        if self.restart_token.is_none()
+            || self.window.is_zero()
+            || message.event.ty() == EventType::Shutdown
+        {
+            return;
+        }
+
+        let now = Instant::now();
+        while self
+            .messages
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+            || self.messages.len() >= REPLAY_CAPACITY
+        {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((now, message.clone()));
+    }
+
+    fn resume(&mut self, plugin_id: &str, restart_token: &str) -> VecDeque<BroadcastMessage> {
+        if self.restart_token.as_deref() != Some(restart_token) {
+            warn!("Subscriber '{plugin_id}' presented an unknown restart token");
+            return VecDeque::new();
+        }
+        if !self.resumed.insert(plugin_id.to_owned()) {
+            return VecDeque::new();
+        }
+
+        let now = Instant::now();
+        self.messages
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= self.window)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+}
+
+// Events caused by an RPC call continue its trace, the others (e.g. from Discord) start a new one
+fn broadcast_trace(ty: EventType) -> Option<TraceContext> {
+    info_span!("broadcast", event_type = ?ty).in_scope(trace::current)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use globibot_core::serenity::all::{ChannelId, MessageId};
+
+    use super::*;
+
+    fn join_group(publisher: &Publisher, balance: Balance) -> mpsc::Receiver<TracedEvent> {
+        let (sender, receiver) = mpsc::channel(16);
+        let request = HandshakeRequest {
+            id: "plugin".to_owned(),
+            events: HashSet::from([EventType::MessageDelete]),
+            topics: HashSet::new(),
+            protocol_version: globibot_core::PROTOCOL_VERSION,
+            restart_token: None,
+            group: Some(ConsumerGroup {
+                id: "group".to_owned(),
+                balance,
+            }),
+        };
+        spawn_subscriber(publisher, &PluginPolicies::default(), request, None, sender);
+        receiver
+    }
+
+    fn assigned_instance(publisher: &Publisher, channel_id: u64) -> u64 {
+        let message = BroadcastMessage {
+            event: Event::MessageDelete {
+                channel_id: ChannelId::new(channel_id),
+                message_id: MessageId::new(1),
+            },
+            trace: None,
+            shard: None,
+            target: None,
+            assignments: <_>::default(),
+            delivery: None,
+        };
+        publisher.assign_groups(&message)["plugin"]["group"]
+    }
+
+    #[tokio::test]
+    async fn round_robin_groups_take_turns() {
+        let (publisher, _queue_feed) = Publisher::new(Duration::ZERO, None);
+        let _instances = [(); 3].map(|_| join_group(&publisher, Balance::RoundRobin));
+
+        let assigned = (0..6)
+            .map(|_| assigned_instance(&publisher, 1))
+            .collect::<Vec<_>>();
+
+        assert_eq!(assigned[..3], assigned[3..]);
+        assert_eq!(assigned[..3].iter().collect::<HashSet<_>>().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn channel_hash_groups_keep_channels_on_one_instance() {
+        let (publisher, _queue_feed) = Publisher::new(Duration::ZERO, None);
+        let _instances = [(); 3].map(|_| join_group(&publisher, Balance::ChannelHash));
+
+        let assigned = (1..=20)
+            .map(|channel_id| assigned_instance(&publisher, channel_id))
+            .collect::<Vec<_>>();
 
-        match self.sender.send(BroadcastMessage { event }) {
-            Ok(count) => debug!("Broadcasted {ty:?} to {count} subscribers"),
-            Err(_) => warn!("Failed to broadcast event"),
+        for (channel_id, instance) in (1..=20).zip(&assigned) {
+            assert_eq!(assigned_instance(&publisher, channel_id), *instance);
         }
+        assert!(assigned.iter().collect::<HashSet<_>>().len() > 1);
     }
 }
@@ -1,65 +1,245 @@
 #![feature(trait_alias)]
 
+mod activity;
+mod admin;
+mod config;
 mod discord;
 mod events;
+mod metrics;
+mod plugin_calls;
+mod presence;
+mod queue;
 mod rpc;
+mod scheduler;
+mod shutdown;
+mod storage;
+mod supervisor;
+#[cfg(feature = "wasm")]
+mod wasm;
 mod web;
 
-use std::{env, io, num::ParseIntError};
+use std::{fs, io, os::unix::fs::PermissionsExt, path::PathBuf, time::Duration};
 
-use futures::TryFutureExt;
-use globibot_core::transport::{Protocol, Tcp};
+use clap::Parser;
+use futures::{FutureExt, TryFutureExt};
+use globibot_core::{
+    rpc::{SchedulerError, StorageError},
+    telemetry,
+    transport::{Address, Protocol},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{Config, ConfigError};
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to the TOML configuration file, defaults to `globibot.toml` when present
+    #[arg(long, env = "GLOBIBOT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Validate the configuration, print it and exit
+    #[arg(long)]
+    check_config: bool,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
-    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+    let config = Config::load(args.config.as_deref())?;
 
-    let publisher = events::Publisher::new();
+    if args.check_config {
+        println!("{config:#?}");
+        return Ok(());
+    }
 
-    let subscriber_addr = env::var("SUBSCRIBER_ADDR")?;
-    let rpc_addr = env::var("RPC_ADDR")?;
+    let _telemetry = telemetry::init("globibot-bot", config.telemetry.otlp_endpoint.as_deref())?;
+    activity::init(&config.web.activity);
 
-    let raw_event_subscribers = Tcp::new(subscriber_addr).listen().await?;
-    let raw_rpc_clients = Tcp::new(rpc_addr).listen().await?;
+    let storage = storage::Storage::open(&config.storage.path)?;
+    let restart_token = shutdown::take_restart_token(&storage).await?;
 
-    let discord_token = env::var("DISCORD_TOKEN")?;
-    let application_id = env::var("APPLICATION_ID")?.parse()?;
-    let mut discord_client =
-        discord::client(&discord_token, publisher.clone(), application_id).await?;
+    let (publisher, queue_feed) = events::Publisher::new(
+        Duration::from_secs(config.shutdown.replay_window_secs),
+        restart_token.clone(),
+    );
+    let policies = config.policies();
+    let supervisor = supervisor::Supervisor::new(policies.clone());
+    let spawners = supervisor::spawners(&config, restart_token.as_deref())?;
+    // Cancelled on SIGTERM, listeners stop accepting new connections
+    let shutting_down = CancellationToken::new();
+    #[cfg(feature = "wasm")]
+    let wasm_hosts = wasm::hosts(&config);
+    #[cfg(not(feature = "wasm"))]
+    if config.plugins.values().any(|plugin| plugin.wasm.is_some()) {
+        tracing::warn!(
+            "WASM plugins are configured but the bot was built without the 'wasm' feature"
+        );
+    }
 
-    let publish_events = events::run_publisher(raw_event_subscribers, publisher);
-    let run_rpc_server = rpc::run_server(
-        raw_rpc_clients,
-        discord_client.cache.clone(),
-        discord_client.http.clone(),
+    let socket_mode = config.listeners.socket_mode;
+    let raw_event_subscribers = listen(config.listeners.subscriber_addr, socket_mode).await?;
+    let raw_rpc_clients = listen(config.listeners.rpc_addr, socket_mode).await?;
+
+    let scheduler = scheduler::Scheduler::load(storage.clone()).await?;
+
+    let presence = presence::PresenceControl::new(&config.discord.presence);
+    let discord_client = discord::client(
+        config.discord.token.expose(),
+        publisher.clone(),
+        presence.clone(),
+        config.discord.application_id,
+        config.discord.intents,
+    )
+    .await?;
+
+    let record_events = {
+        let record_path = config.events.record_path;
+        let publisher = publisher.clone();
+        async move {
+            match record_path {
+                Some(path) => events::run_recorder(path, publisher).await,
+                None => Ok(()),
+            }
+        }
+    };
+    let run_scheduler = scheduler::run_scheduler(scheduler.clone(), publisher.clone());
+    let queue = queue::EventQueue::load(storage.clone(), &config.events.queue).await?;
+    let fill_queues = events::run_queue(queue.clone(), queue_feed);
+    let publish_events = events::run_publisher(
+        raw_event_subscribers,
+        publisher.clone(),
+        policies.clone(),
+        queue,
+        shutting_down.clone(),
     );
-    let run_discord_client = discord_client.start();
-    let run_web_server = web::run_server();
+    let services = rpc::Services {
+        discord_http: discord_client.http.clone(),
+        discord_cache: discord_client.cache.clone(),
+        storage: storage.clone(),
+        scheduler,
+        publisher: publisher.clone(),
+        plugin_calls: <_>::default(),
+        rpc_clients: <_>::default(),
+        commands: <_>::default(),
+        presence: presence.clone(),
+        webhooks: <_>::default(),
+    };
+    #[cfg(feature = "wasm")]
+    let host_wasm_plugins = wasm::run_hosts(wasm_hosts, services.clone(), policies.clone());
+    #[cfg(not(feature = "wasm"))]
+    let host_wasm_plugins = futures::future::ok::<_, AppError>(());
+    let admin = admin::Admin::new(&config.web.admin, services.clone(), supervisor.clone());
+    let rpc_clients = services.rpc_clients.clone();
+    let run_rpc_server =
+        rpc::run_server(raw_rpc_clients, services, policies, shutting_down.clone());
+    let expire_storage = storage::run_expiry(storage.clone());
+    let shard_manager = discord_client.shard_manager.clone();
+    let monitor_shards = discord::run_shard_monitor(shard_manager.clone());
+    let update_presence = presence::run_presence(presence, shard_manager.clone());
+    let run_discord_client = discord::start(discord_client, config.discord.shards);
+    let run_web_server = {
+        let web = config.web;
+        let supervisor = supervisor.clone();
+        let shutting_down = shutting_down.clone();
+        async move {
+            match web.enabled {
+                true => web::run_server(web.addr, supervisor, admin, shutting_down).await,
+                false => Ok(()),
+            }
+        }
+    };
+    let supervise_plugins = supervisor::run_supervisor(supervisor, spawners, shutting_down.clone());
+
+    let shutdown_config = config.shutdown;
+    let shut_down = async move {
+        shutdown::wait_for_signal().await?;
+        shutting_down.cancel();
+        shutdown::run(
+            &shutdown_config,
+            &publisher,
+            &rpc_clients,
+            &shard_manager,
+            &storage,
+        )
+        .await?;
+        Ok::<_, AppError>(())
+    };
 
     tracing::info!("Starting bot...");
 
-    futures::try_join!(
-        publish_events.err_into::<AppError>(),
-        run_rpc_server.err_into(),
-        run_discord_client.err_into(),
-        run_web_server.err_into(),
-    )?;
+    let run_bot = async {
+        futures::try_join!(
+            publish_events.err_into::<AppError>(),
+            record_events.err_into(),
+            run_rpc_server.err_into(),
+            expire_storage.err_into(),
+            run_scheduler.err_into(),
+            run_discord_client.err_into(),
+            run_web_server.err_into(),
+            supervise_plugins.map(Ok::<_, AppError>),
+            monitor_shards.map(Ok::<_, AppError>),
+            update_presence.map(Ok::<_, AppError>),
+            fill_queues.map(Ok::<_, AppError>),
+            host_wasm_plugins.err_into(),
+        )
+    };
+
+    // Everything keeps running while shutting down, until the plugins and the gateway are done
+    tokio::select! {
+        result = run_bot => result.map(drop),
+        result = shut_down => {
+            tracing::info!("Shut down gracefully");
+            result
+        }
+    }
+}
+
+async fn listen(
+    address: Address,
+    socket_mode: Option<u32>,
+) -> io::Result<<Address as Protocol>::ClientStream> {
+    let socket_path = match &address {
+        Address::Unix(path) => Some(path.clone()),
+        Address::Tcp(_) => None,
+    };
+
+    if let Some(parent) = socket_path.as_ref().and_then(|path| path.parent()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    tracing::info!("Listening on {address}");
+    let clients = address.listen().await?;
+
+    if let Some((path, mode)) = socket_path.zip(socket_mode) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
 
-    Ok(())
+    Ok(clients)
 }
+
 #[derive(Debug, thiserror::Error)]
 enum AppError {
     #[error("IO error: {0}")]
     IO(#[from] io::Error),
 
+    #[error("{0}")]
+    Storage(#[from] StorageError),
+
+    #[error("{0}")]
+    Scheduler(#[from] SchedulerError),
+
     #[error("Discord error: {0}")]
     Discord(Box<globibot_core::serenity::Error>),
 
-    #[error("Missing environment variable: {0}")]
-    MissingEnvVar(#[from] env::VarError),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+
+    #[error("Telemetry error: {0}")]
+    Telemetry(#[from] telemetry::ExporterBuildError),
 
-    #[error("Malformed application ID: {0}")]
-    MalformedApplicationId(#[from] ParseIntError),
+    #[cfg(feature = "wasm")]
+    #[error("WASM runtime error: {0}")]
+    Wasm(#[from] wasmtime::Error),
 }
 
 impl From<globibot_core::serenity::Error> for AppError {
@@ -0,0 +1,95 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder, register_gauge_vec,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+};
+
+pub static EVENTS_BROADCAST: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_events_broadcast_total",
+        "Events published to subscribers",
+        &["event_type"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static EVENTS_DELIVERED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_events_delivered_total",
+        "Events sent to a subscriber",
+        &["plugin"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static EVENTS_DROPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_events_dropped_total",
+        "Events that could not be sent to a subscriber",
+        &["plugin", "reason"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static RPC_CALLS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_rpc_calls_total",
+        "RPC calls handled",
+        &["plugin", "method"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static RPC_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_rpc_errors_total",
+        "RPC calls that returned an error",
+        &["plugin", "method"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static RPC_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "globibot_rpc_duration_seconds",
+        "Time spent handling RPC calls",
+        &["plugin", "method"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static CONNECTED_PLUGINS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "globibot_connected_plugins",
+        "Plugins currently connected",
+        &["endpoint"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static GATEWAY_LATENCY: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "globibot_gateway_latency_seconds",
+        "Latency of the last gateway heartbeat",
+        &["shard"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub static DISCORD_RATELIMITS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "globibot_discord_ratelimits_total",
+        "Discord HTTP requests that hit a rate limit",
+        &["global"]
+    )
+    .expect("Metric should only be registered once")
+});
+
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Text encoding should not fail");
+    String::from_utf8(buffer).expect("Text encoding should produce UTF-8")
+}
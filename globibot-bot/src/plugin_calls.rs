@@ -0,0 +1,210 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use globibot_core::{
+    events::{Event, EventType},
+    rpc::{CallId, PluginCallError, PluginCallResult},
+};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::events::Publisher;
+
+#[derive(Clone, Default)]
+pub struct PluginCalls {
+    next_id: Arc<AtomicU64>,
+    pending: Arc<parking_lot::Mutex<HashMap<CallId, PendingCall>>>,
+}
+
+struct PendingCall {
+    target: String,
+    reply: oneshot::Sender<Result<Value, String>>,
+}
+
+impl PluginCalls {
+    pub async fn call(
+        &self,
+        publisher: &Publisher,
+        source: String,
+        target: String,
+        method: String,
+        payload: Value,
+        deadline: Instant,
+    ) -> PluginCallResult<Value> {
+        // The caller would be waiting on itself until the deadline
+        if source == target {
+            return Err(PluginCallError(format!(
+                "Plugin '{target}' cannot call itself"
+            )));
+        }
+
+        if !publisher.is_subscribed(&target, EventType::PluginCall) {
+            return Err(PluginCallError(format!(
+                "Plugin '{target}' is not accepting calls"
+            )));
+        }
+
+        let call_id = CallId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (reply, response) = oneshot::channel();
+        self.pending.lock().insert(
+            call_id,
+            PendingCall {
+                target: target.clone(),
+                reply,
+            },
+        );
+
+        publisher.send_to(
+            &target,
+            Event::PluginCall {
+                call_id,
+                source,
+                method,
+                payload,
+            },
+        );
+
+        let response = tokio::time::timeout_at(deadline.into(), response).await;
+        self.pending.lock().remove(&call_id);
+
+        match response {
+            Ok(Ok(result)) => result.map_err(PluginCallError),
+            Ok(Err(_abandoned)) => Err(PluginCallError(format!(
+                "Plugin '{target}' abandoned the call"
+            ))),
+            Err(_timed_out) => Err(PluginCallError(format!(
+                "Plugin '{target}' did not respond in time"
+            ))),
+        }
+    }
+
+    pub fn respond(
+        &self,
+        responder: &str,
+        call_id: CallId,
+        result: Result<Value, String>,
+    ) -> PluginCallResult<()> {
+        let call = match self.pending.lock().entry(call_id) {
+            Entry::Occupied(call) if call.get().target == responder => call.remove(),
+            _ => return Err(PluginCallError(format!("Unknown call: {call_id:?}"))),
+        };
+
+        call.reply
+            .send(result)
+            .map_err(|_| PluginCallError("Caller is gone".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use futures::{StreamExt, channel::mpsc};
+    use globibot_core::events::{HandshakeRequest, TracedEvent};
+
+    use super::*;
+    use crate::{config::PluginPolicies, events::spawn_subscriber};
+
+    fn subscribe(publisher: &Publisher, plugin_id: &str) -> mpsc::Receiver<TracedEvent> {
+        let (sender, receiver) = mpsc::channel(16);
+        let request = HandshakeRequest {
+            id: plugin_id.to_owned(),
+            events: HashSet::from([EventType::PluginCall]),
+            topics: HashSet::new(),
+            protocol_version: globibot_core::PROTOCOL_VERSION,
+            restart_token: None,
+            group: None,
+        };
+        spawn_subscriber(publisher, &PluginPolicies::default(), request, None, sender);
+        receiver
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    // Each plugin handles the other's call while its own is still pending
+    #[tokio::test]
+    async fn plugins_calling_each_other_get_their_responses() {
+        let (publisher, _queue_feed) = Publisher::new(Duration::ZERO, None);
+        let calls = PluginCalls::default();
+        let mut alpha_events = subscribe(&publisher, "alpha");
+        let mut beta_events = subscribe(&publisher, "beta");
+
+        tokio::spawn({
+            let calls = calls.clone();
+            async move {
+                while let Some(TracedEvent { event, .. }) = alpha_events.next().await {
+                    if let Event::PluginCall { call_id, .. } = event {
+                        calls
+                            .respond("alpha", call_id, Ok(Value::from("pong")))
+                            .unwrap();
+                    }
+                }
+            }
+        });
+        tokio::spawn({
+            let calls = calls.clone();
+            let publisher = publisher.clone();
+            async move {
+                while let Some(TracedEvent { event, .. }) = beta_events.next().await {
+                    if let Event::PluginCall {
+                        call_id, source, ..
+                    } = event
+                    {
+                        let result = calls
+                            .call(
+                                &publisher,
+                                "beta".to_owned(),
+                                source,
+                                "ping".to_owned(),
+                                Value::Null,
+                                deadline(),
+                            )
+                            .await
+                            .map_err(|why| why.0);
+                        calls.respond("beta", call_id, result).unwrap();
+                    }
+                }
+            }
+        });
+
+        let response = calls
+            .call(
+                &publisher,
+                "alpha".to_owned(),
+                "beta".to_owned(),
+                "ping".to_owned(),
+                Value::Null,
+                deadline(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response, "pong");
+    }
+
+    #[tokio::test]
+    async fn plugins_cannot_call_themselves() {
+        let (publisher, _queue_feed) = Publisher::new(Duration::ZERO, None);
+        let calls = PluginCalls::default();
+        let _events = subscribe(&publisher, "alpha");
+
+        let response = calls
+            .call(
+                &publisher,
+                "alpha".to_owned(),
+                "alpha".to_owned(),
+                "ping".to_owned(),
+                Value::Null,
+                deadline(),
+            )
+            .await;
+        assert!(response.is_err());
+    }
+}
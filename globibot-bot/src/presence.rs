@@ -0,0 +1,138 @@
+use std::{sync::Arc, time::Duration};
+
+use globibot_core::{
+    rpc::{Activity, Presence, PresenceStatus},
+    serenity::all::{ActivityData, OnlineStatus, ShardManager},
+};
+use tokio::{sync::Notify, time::Instant};
+use tracing::info;
+
+use crate::config;
+
+// Plugins may set presences faster than that, only the latest one is sent once the interval is up
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(config::MIN_PRESENCE_ROTATION_SECS);
+
+// Presence shown on every shard: the one set by a plugin if any, otherwise the configured rotation
+#[derive(Debug, Clone)]
+pub struct PresenceControl {
+    state: Arc<parking_lot::Mutex<State>>,
+    changed: Arc<Notify>,
+    rotation_interval: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    status: PresenceStatus,
+    activities: Vec<Activity>,
+    position: usize,
+    // Along with the plugin that set it
+    overridden: Option<(String, Presence)>,
+}
+
+impl PresenceControl {
+    pub fn new(config: &config::Presence) -> Self {
+        let state = State {
+            status: config.status,
+            activities: config.activities.clone(),
+            position: 0,
+            overridden: None,
+        };
+
+        Self {
+            state: Arc::new(parking_lot::Mutex::new(state)),
+            changed: <_>::default(),
+            rotation_interval: Duration::from_secs(config.rotation_interval_secs),
+        }
+    }
+
+    pub fn current(&self) -> Presence {
+        let state = self.state.lock();
+        match &state.overridden {
+            Some((_, presence)) => presence.clone(),
+            None => Presence {
+                status: state.status,
+                activity: state.activities.get(state.position).cloned(),
+            },
+        }
+    }
+
+    pub fn set(&self, plugin_id: &str, presence: Option<Presence>) {
+        match &presence {
+            Some(presence) => info!("Presence set to {presence:?} by '{plugin_id}'"),
+            None => info!("Presence reset to the configured one by '{plugin_id}'"),
+        }
+        self.state.lock().overridden = presence.map(|presence| (plugin_id.to_owned(), presence));
+        self.changed.notify_one();
+    }
+
+    // Goes back to the configured presence when the plugin that set the current one goes away
+    pub fn release(&self, plugin_id: &str) {
+        let mut state = self.state.lock();
+        if state
+            .overridden
+            .as_ref()
+            .is_some_and(|(owner, _)| owner == plugin_id)
+        {
+            info!("Presence reset to the configured one, '{plugin_id}' disconnected");
+            state.overridden = None;
+            self.changed.notify_one();
+        }
+    }
+
+    fn rotate(&self) {
+        let mut state = self.state.lock();
+        if !state.activities.is_empty() {
+            state.position = (state.position + 1) % state.activities.len();
+        }
+    }
+}
+
+pub async fn run_presence(control: PresenceControl, shard_manager: Arc<ShardManager>) {
+    let mut rotation = tokio::time::interval(control.rotation_interval);
+    rotation.tick().await;
+    let mut applied: Option<(Presence, Instant)> = None;
+
+    loop {
+        let presence = control.current();
+        let mut throttled_until = None;
+        match &applied {
+            Some((current, _)) if *current == presence => {}
+            Some((_, at)) if at.elapsed() < MIN_UPDATE_INTERVAL => {
+                throttled_until = Some(*at + MIN_UPDATE_INTERVAL);
+            }
+            _ => {
+                let (activity, status) = gateway_presence(&presence);
+                for runner in shard_manager.runners.lock().await.values() {
+                    runner.runner_tx.set_presence(activity.clone(), status);
+                }
+                applied = Some((presence, Instant::now()));
+            }
+        }
+
+        tokio::select! {
+            _ = rotation.tick() => control.rotate(),
+            _ = control.changed.notified() => {}
+            _ = tokio::time::sleep_until(throttled_until.unwrap_or_else(Instant::now)),
+                if throttled_until.is_some() => {}
+        }
+    }
+}
+
+// Shards connecting later pick the presence up when they're ready
+pub fn gateway_presence(presence: &Presence) -> (Option<ActivityData>, OnlineStatus) {
+    let activity = presence.activity.as_ref().map(|activity| match activity {
+        Activity::Playing { name } => ActivityData::playing(name),
+        Activity::Listening { name } => ActivityData::listening(name),
+        Activity::Watching { name } => ActivityData::watching(name),
+        Activity::Competing { name } => ActivityData::competing(name),
+        Activity::Custom { state } => ActivityData::custom(state),
+    });
+    let status = match presence.status {
+        PresenceStatus::Online => OnlineStatus::Online,
+        PresenceStatus::Idle => OnlineStatus::Idle,
+        PresenceStatus::DoNotDisturb => OnlineStatus::DoNotDisturb,
+        PresenceStatus::Invisible => OnlineStatus::Invisible,
+    };
+
+    (activity, status)
+}
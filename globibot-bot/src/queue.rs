@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use globibot_core::{
+    events::{Event, EventType, TracedEvent},
+    rpc::StorageResult,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::{
+    config,
+    storage::{BOT_NAMESPACE, QueuedEvent, Storage},
+};
+
+// Interests are persisted so that events keep being queued while their plugin is away
+const INTEREST_KEY_PREFIX: &str = "queue_interest:";
+
+#[derive(Clone)]
+pub struct EventQueue {
+    storage: Storage,
+    retention: Duration,
+    max_events: u64,
+    plugins: Arc<parking_lot::Mutex<HashMap<String, QueuedPlugin>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interest {
+    pub events: HashSet<EventType>,
+    pub topics: HashSet<String>,
+}
+
+struct QueuedPlugin {
+    interest: Interest,
+    notify: Arc<Notify>,
+}
+
+impl EventQueue {
+    pub async fn load(storage: Storage, config: &config::EventQueue) -> StorageResult<Self> {
+        let interests = storage
+            .list(BOT_NAMESPACE.to_owned(), INTEREST_KEY_PREFIX.to_owned())
+            .await?;
+
+        let plugins = interests
+            .into_iter()
+            .filter_map(|(key, interest)| {
+                let plugin_id = key.strip_prefix(INTEREST_KEY_PREFIX)?.to_owned();
+                let interest = serde_json::from_value(interest)
+                    .inspect_err(|why| warn!("Invalid queue interest for '{plugin_id}': {why}"))
+                    .ok()?;
+                let queued = QueuedPlugin {
+                    interest,
+                    notify: <_>::default(),
+                };
+                Some((plugin_id, queued))
+            })
+            .collect();
+
+        Ok(Self {
+            storage,
+            retention: Duration::from_secs(config.retention_secs),
+            max_events: config.max_events,
+            plugins: Arc::new(parking_lot::Mutex::new(plugins)),
+        })
+    }
+
+    // Notified whenever an event is queued for the plugin
+    pub async fn register(
+        &self,
+        plugin_id: &str,
+        interest: Interest,
+    ) -> StorageResult<Arc<Notify>> {
+        let value = serde_json::to_value(&interest).unwrap_or_default();
+        self.storage
+            .set(
+                BOT_NAMESPACE.to_owned(),
+                format!("{INTEREST_KEY_PREFIX}{plugin_id}"),
+                value,
+                None,
+            )
+            .await?;
+
+        let mut plugins = self.plugins.lock();
+        let queued = plugins
+            .entry(plugin_id.to_owned())
+            .or_insert_with(|| QueuedPlugin {
+                interest: interest.clone(),
+                notify: <_>::default(),
+            });
+        queued.interest = interest;
+        Ok(Arc::clone(&queued.notify))
+    }
+
+    // Number of plugins the event was queued for
+    pub async fn enqueue(&self, event: &TracedEvent, target: Option<&str>) -> usize {
+        let recipients = self
+            .plugins
+            .lock()
+            .iter()
+            .filter(|(plugin_id, queued)| {
+                target.is_none_or(|target| target == plugin_id.as_str())
+                    && queued.interest.wants(&event.event)
+            })
+            .map(|(plugin_id, queued)| (plugin_id.clone(), Arc::clone(&queued.notify)))
+            .collect::<Vec<_>>();
+
+        let mut queued_for = 0;
+        for (plugin_id, notify) in recipients {
+            let queued = QueuedEvent {
+                at: SystemTime::now(),
+                event: event.clone(),
+            };
+            match self
+                .storage
+                .push_event(plugin_id.clone(), &queued, self.max_events)
+                .await
+            {
+                Ok(_) => {
+                    notify.notify_one();
+                    queued_for += 1;
+                }
+                Err(why) => warn!("Failed to queue event for '{plugin_id}': {why}"),
+            }
+        }
+
+        queued_for
+    }
+
+    pub async fn pending(
+        &self,
+        plugin_id: &str,
+        from: u64,
+        limit: usize,
+    ) -> StorageResult<Vec<(u64, QueuedEvent)>> {
+        self.storage
+            .queued_events(plugin_id.to_owned(), from, limit)
+            .await
+    }
+
+    pub async fn ack(&self, plugin_id: &str, ack_id: u64) -> StorageResult<bool> {
+        self.storage.ack_event(plugin_id.to_owned(), ack_id).await
+    }
+
+    pub async fn purge(&self) -> StorageResult<()> {
+        let older_than = SystemTime::now() - self.retention;
+        self.storage.purge_queued_events(older_than).await
+    }
+}
+
+impl Interest {
+    fn wants(&self, event: &Event) -> bool {
+        match event {
+            Event::Topic { topic, .. } => self.topics.contains(topic),
+            // Only meant for the current connections
+            Event::Shutdown { .. } => false,
+            event => self.events.contains(&event.ty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic_event(payload: u64) -> TracedEvent {
+        TracedEvent::from(Event::Topic {
+            topic: "test".to_owned(),
+            source: "test".to_owned(),
+            payload: payload.into(),
+        })
+    }
+
+    // Subscribers read on from the sequence number following the last event they were sent
+    #[tokio::test]
+    async fn events_queued_once_emptied_are_delivered() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path().join("globibot.redb")).unwrap();
+        let config = config::EventQueue {
+            retention_secs: 60,
+            max_events: 10,
+        };
+        let queue = EventQueue::load(storage, &config).await.unwrap();
+        let interest = Interest {
+            events: HashSet::new(),
+            topics: HashSet::from(["test".to_owned()]),
+        };
+        queue.register("plugin", interest).await.unwrap();
+
+        queue.enqueue(&topic_event(1), None).await;
+        queue.enqueue(&topic_event(2), None).await;
+        let delivered = queue.pending("plugin", 0, 10).await.unwrap();
+        for (seq, _) in &delivered {
+            assert!(queue.ack("plugin", *seq).await.unwrap());
+        }
+        let next_seq = delivered.last().unwrap().0 + 1;
+
+        queue.enqueue(&topic_event(3), None).await;
+        let pending = queue.pending("plugin", next_seq, 10).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(matches!(
+            &pending[0].1.event.event,
+            Event::Topic { payload, .. } if payload == 3
+        ));
+    }
+}
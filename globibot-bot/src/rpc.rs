@@ -1,15 +1,28 @@
-use std::{io, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use futures::{Stream, StreamExt};
-use globibot_core::rpc::{self, AcceptError, DiscordApiError, TypingKey};
+use globibot_core::events::Event;
+use globibot_core::rpc::{
+    self, AcceptError, CallId, DiscordApiError, JobId, JobSchedule, PluginCallError,
+    PluginCallResult, PollMessage, Presence, ScheduledJob, SchedulerResult, StorageResult,
+    TypingKey, WebhookMessage,
+};
 use globibot_core::serenity::all::{
-    CommandId, CommandOption, CreateAttachment, CreateMessage, EditMessage, InteractionId, Typing,
-    UserId,
+    ChannelType, CommandId, CommandOption, CreateAttachment, CreateEmbed, CreateForumPost,
+    CreateMessage, CreatePoll, CreatePollAnswer, CreateThread, CreateWebhook, EditMessage,
+    EditThread, Embed, ExecuteWebhook, ForumTagId, GuildChannel, InteractionId, ScheduledEvent,
+    ScheduledEventId, Typing, UserId, Webhook, WebhookId,
 };
 use globibot_core::serenity::model::prelude::{Channel as DiscordChannel, User};
 use globibot_core::serenity::{
+    self,
     cache::Cache as DiscordCache,
-    http::Http as DiscordHttp,
+    http::{Http as DiscordHttp, HttpError, StatusCode},
     model::{
         application::Command,
         channel::{Message, ReactionType},
@@ -18,43 +31,320 @@ use globibot_core::serenity::{
     },
     utils::{self, ContentSafeOptions},
 };
+use globibot_core::transport::PeerAddr;
 use serde::Deserialize;
-use tarpc::{ChannelError, context::Context, server::Channel};
-use tokio::io::{AsyncRead, AsyncWrite};
+use serde_json::Value;
+use tarpc::{
+    ChannelError, RequestName, ServerError,
+    context::Context,
+    server::{Channel, Serve},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::OnceCell,
+    task::JoinSet,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-use rpc::{DiscordApiResult, Protocol, ServerChannel};
+use rpc::{
+    DiscordApiResult, Protocol, ProtocolRequest, ProtocolResponse, ServeProtocol, ServerChannel,
+};
 use tracing::{debug, info, warn};
 
-use crate::web::WEB_STATE;
+use crate::{
+    activity,
+    config::PluginPolicies,
+    events::Publisher,
+    metrics,
+    plugin_calls::PluginCalls,
+    presence::PresenceControl,
+    scheduler::Scheduler,
+    storage::{self, Storage},
+    web::{self, RpcEndpoint, WEB_STATE},
+};
+
+#[derive(Clone)]
+pub struct Services {
+    pub discord_http: Arc<DiscordHttp>,
+    pub discord_cache: Arc<DiscordCache>,
+    pub storage: Storage,
+    pub scheduler: Scheduler,
+    pub publisher: Publisher,
+    pub plugin_calls: PluginCalls,
+    pub rpc_clients: RpcClients,
+    pub commands: CommandRegistry,
+    pub presence: PresenceControl,
+    pub webhooks: WebhookCache,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RpcClients {
+    // Connections of each plugin by generation, replicas of a plugin connect side by side
+    clients: Arc<parking_lot::Mutex<HashMap<String, HashMap<u64, CancellationToken>>>>,
+    tasks: TaskTracker,
+}
+
+type PluginCommands = HashMap<(Option<GuildId>, String), Value>;
+
+// Slash commands as last sent by each plugin, so they can be pushed to Discord again
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    commands: Arc<parking_lot::Mutex<HashMap<String, PluginCommands>>>,
+}
+
+impl Services {
+    pub fn server(&self, plugin_id: String) -> Server {
+        Server {
+            plugin_id,
+            discord_http: Arc::clone(&self.discord_http),
+            discord_cache: Arc::clone(&self.discord_cache),
+            storage: self.storage.clone(),
+            scheduler: self.scheduler.clone(),
+            publisher: self.publisher.clone(),
+            plugin_calls: self.plugin_calls.clone(),
+            commands: self.commands.clone(),
+            presence: self.presence.clone(),
+            webhooks: self.webhooks.clone(),
+
+            typings: <_>::default(),
+        }
+    }
+
+    pub async fn resync_commands(&self, plugin_id: &str) -> DiscordApiResult<usize> {
+        let commands = self.commands.registered(plugin_id);
+        let count = commands.len();
+
+        for (guild_id, data) in commands {
+            let server = self.server(plugin_id.to_owned());
+            let ctx = tarpc::context::current();
+            match guild_id {
+                Some(guild_id) => server.upsert_guild_command(ctx, guild_id, data).await?,
+                None => server.upsert_global_command(ctx, data).await?,
+            };
+        }
+
+        Ok(count)
+    }
+}
+
+impl RpcClients {
+    fn register(&self, plugin_id: &str) -> (u64, CancellationToken) {
+        let generation = web::next_instance();
+        let token = CancellationToken::new();
+        self.clients
+            .lock()
+            .entry(plugin_id.to_owned())
+            .or_default()
+            .insert(generation, token.clone());
+        (generation, token)
+    }
+
+    // Whether that was the plugin's last connection
+    fn unregister(&self, plugin_id: &str, generation: u64) -> bool {
+        let mut clients = self.clients.lock();
+        let Some(instances) = clients.get_mut(plugin_id) else {
+            return false;
+        };
+        instances.remove(&generation);
+        if !instances.is_empty() {
+            return false;
+        }
+        clients.remove(plugin_id);
+        true
+    }
+
+    // Disconnects every instance of the plugin
+    pub fn disconnect(&self, plugin_id: &str) -> bool {
+        match self.clients.lock().get(plugin_id) {
+            Some(instances) => {
+                instances.values().for_each(CancellationToken::cancel);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Clients stop reading requests but finish the one they're handling
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        for token in self.clients.lock().values().flat_map(HashMap::values) {
+            token.cancel();
+        }
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+}
+
+// Webhooks the bot posts through, by channel
+#[derive(Debug, Clone, Default)]
+pub struct WebhookCache {
+    // Concurrent calls for a channel wait on its cell rather than each creating a webhook, other
+    // channels aren't held up meanwhile
+    webhooks: Arc<parking_lot::Mutex<HashMap<ChannelId, Arc<OnceCell<Webhook>>>>>,
+}
+
+const WEBHOOK_NAME: &str = "Globibot";
+// Discord's JSON error code for webhooks that were deleted
+const UNKNOWN_WEBHOOK: isize = 10015;
+// 32 days
+const POLL_MAX_HOURS: u64 = 768;
+
+impl WebhookCache {
+    async fn get_or_create(
+        &self,
+        http: &Arc<DiscordHttp>,
+        bot_id: UserId,
+        chan_id: ChannelId,
+    ) -> DiscordApiResult<Webhook> {
+        let cell = Arc::clone(self.webhooks.lock().entry(chan_id).or_default());
+        let webhook = cell
+            .get_or_try_init(async || {
+                // Reusing the one created before a restart of the bot
+                let existing = chan_id.webhooks(http).await?.into_iter().find(|webhook| {
+                    webhook.token.is_some()
+                        && webhook.user.as_ref().is_some_and(|user| user.id == bot_id)
+                });
+                match existing {
+                    Some(webhook) => Ok(webhook),
+                    None => {
+                        chan_id
+                            .create_webhook(http, CreateWebhook::new(WEBHOOK_NAME))
+                            .await
+                    }
+                }
+            })
+            .await?;
+
+        Ok(webhook.clone())
+    }
+
+    // The webhook was deleted from Discord or its token revoked, it's looked up again next time
+    fn evict_if_gone(&self, chan_id: ChannelId, err: &serenity::Error) {
+        let gone = match err {
+            serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+                response.status_code == StatusCode::UNAUTHORIZED
+                    || (response.status_code == StatusCode::NOT_FOUND
+                        && response.error.code == UNKNOWN_WEBHOOK)
+            }
+            _ => false,
+        };
+        if gone {
+            self.webhooks.lock().remove(&chan_id);
+        }
+    }
+}
+
+impl CommandRegistry {
+    fn remember(&self, plugin_id: &str, guild_id: Option<GuildId>, data: &Value) {
+        let Some(name) = data.get("name").and_then(Value::as_str) else {
+            return;
+        };
+        self.commands
+            .lock()
+            .entry(plugin_id.to_owned())
+            .or_default()
+            .insert((guild_id, name.to_owned()), data.clone());
+    }
+
+    // Edits may only carry the fields that changed, they're applied over what was last sent
+    fn remember_edit(
+        &self,
+        plugin_id: &str,
+        guild_id: Option<GuildId>,
+        name: &str,
+        changes: &Value,
+    ) {
+        let mut commands = self.commands.lock();
+        let data = commands
+            .entry(plugin_id.to_owned())
+            .or_default()
+            .entry((guild_id, name.to_owned()))
+            .or_insert_with(|| serde_json::json!({ "name": name }));
+        if let (Some(data), Some(changes)) = (data.as_object_mut(), changes.as_object()) {
+            data.extend(changes.clone());
+        }
+    }
+
+    fn registered(&self, plugin_id: &str) -> Vec<(Option<GuildId>, Value)> {
+        self.commands
+            .lock()
+            .get(plugin_id)
+            .into_iter()
+            .flatten()
+            .map(|((guild_id, _), data)| (*guild_id, data.clone()))
+            .collect()
+    }
+}
 
 pub async fn run_server<S, T>(
     transports: S,
-    cache: Arc<DiscordCache>,
-    http: Arc<DiscordHttp>,
+    services: Services,
+    policies: PluginPolicies,
+    shutdown: CancellationToken,
 ) -> io::Result<()>
 where
     S: Stream<Item = io::Result<T>>,
-    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: AsyncRead + AsyncWrite + PeerAddr + Unpin + Send + 'static,
 {
     let mut transports = std::pin::pin!(transports);
 
-    while let Some(transport_result) = transports.next().await {
+    while let Some(transport_result) = shutdown
+        .run_until_cancelled(transports.next())
+        .await
+        .flatten()
+    {
         let transport = transport_result?;
+        let remote_addr = transport.peer_addr();
         match rpc::accept(Default::default(), transport).await {
+            Ok((request, _)) if storage::is_reserved_id(&request.id) => {
+                warn!(
+                    "Rejected RPC client '{id}': plugin ID is reserved",
+                    id = request.id
+                );
+            }
             Ok((request, client)) => {
-                let http = Arc::clone(&http);
-                let cache = Arc::clone(&cache);
-                let handle_client = respond_to_rpc_client(client, http, cache);
-                tokio::spawn({
+                let policy = policies.get(&request.id);
+                if !policy.enabled || !policy.rpc {
+                    warn!(
+                        "Rejected RPC client '{id}': not allowed by policy",
+                        id = request.id
+                    );
+                    continue;
+                }
+
+                let server = services.server(request.id.clone());
+                let typings = server.typings.clone();
+                let (generation, disconnect) = services.rpc_clients.register(&request.id);
+                let handle_client = respond_to_rpc_client(client, server, disconnect);
+                WEB_STATE.lock().unwrap().register_plugin_rpc(
+                    &request.id,
+                    request.protocol_version,
+                    RpcEndpoint {
+                        instance: Some(generation),
+                        connected_at: SystemTime::now(),
+                        remote_addr,
+                    },
+                );
+                services.rpc_clients.tasks.spawn({
                     let plugin_id = request.id.clone();
+                    let rpc_clients = services.rpc_clients.clone();
+                    let presence = services.presence.clone();
                     async move {
                         if let Err(err) = handle_client.await {
                             warn!("RPC client error: {err}");
                         }
-                        WEB_STATE.lock().unwrap().remove_plugin(&plugin_id);
+                        // A plugin that went away can't stop its typing indicators
+                        typings.clear();
+                        if rpc_clients.unregister(&plugin_id, generation) {
+                            presence.release(&plugin_id);
+                        }
+                        WEB_STATE
+                            .lock()
+                            .unwrap()
+                            .remove_plugin_rpc(&plugin_id, Some(generation));
                     }
                 });
-                WEB_STATE.lock().unwrap().register_plugin_rpc(&request.id);
                 info!("New RPC client spawned: '{id}'", id = request.id);
             }
             Err(AcceptError::IO(err)) => {
@@ -64,6 +354,9 @@ where
             Err(AcceptError::HandshakeTimedOut) => {
                 warn!("RPC client did not send a handshake in time")
             }
+            Err(err @ AcceptError::IncompatibleProtocol { .. }) => {
+                warn!("Rejected RPC client: {err}");
+            }
         }
     }
 
@@ -72,26 +365,27 @@ where
 
 async fn respond_to_rpc_client<Transport>(
     client: ServerChannel<Transport>,
-    http: Arc<DiscordHttp>,
-    cache: Arc<DiscordCache>,
+    server: Server,
+    disconnect: CancellationToken,
 ) -> Result<(), ChannelError<io::Error>>
 where
     Transport: AsyncRead + AsyncWrite,
 {
-    let server = Server {
-        discord_http: http,
-        discord_cache: cache,
-
-        typings: <_>::default(),
-    };
-
-    let serve = server.serve();
+    let serve = server.metered();
     let mut requests = std::pin::pin!(client.requests());
+    // Requests run concurrently so that plugins calling each other don't deadlock, the ones still
+    // in flight are aborted along with the connection
+    let mut in_flight = JoinSet::new();
 
-    while let Some(request_result) = requests.next().await {
+    while let Some(request_result) = disconnect
+        .run_until_cancelled(requests.next())
+        .await
+        .flatten()
+    {
         debug!("Handling RPC request");
         let request = request_result?;
-        request.execute(serve.clone()).await;
+        while in_flight.try_join_next().is_some() {}
+        in_flight.spawn(request.execute(serve.clone()));
     }
 
     info!("Ended connection with RPC client");
@@ -100,11 +394,200 @@ where
 }
 
 #[derive(Clone)]
-struct Server {
+pub struct Metered<S> {
+    plugin_id: String,
+    inner: S,
+}
+
+impl<S> Serve for Metered<S>
+where
+    S: Serve<Req = ProtocolRequest, Resp = ProtocolResponse>,
+{
+    type Req = ProtocolRequest;
+    type Resp = ProtocolResponse;
+
+    async fn serve(
+        self,
+        ctx: Context,
+        req: ProtocolRequest,
+    ) -> Result<ProtocolResponse, ServerError> {
+        let method = req.name().to_owned();
+        let labels = [self.plugin_id.as_str(), method.as_str()];
+        let request = activity::capture_request(&req);
+
+        let started_at = Instant::now();
+        let result = self.inner.serve(ctx, req).await;
+        let duration = started_at.elapsed();
+        metrics::RPC_DURATION
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+
+        let error = match &result {
+            Ok(response) => response_error(response),
+            Err(why) => Some(why.detail.clone()),
+        };
+        metrics::RPC_CALLS.with_label_values(&labels).inc();
+        if error.is_some() {
+            metrics::RPC_ERRORS.with_label_values(&labels).inc();
+        }
+        if let Some(request) = request {
+            activity::record_rpc(&self.plugin_id, &method, request, duration, error.clone());
+        }
+
+        web::record_rpc_call(&self.plugin_id, &method);
+        if let Some(error) = error {
+            WEB_STATE
+                .lock()
+                .unwrap()
+                .record_rpc_error(&self.plugin_id, &method, error);
+        }
+        if let Ok(response) = &result
+            && let Some(command) = registered_command(response)
+        {
+            WEB_STATE
+                .lock()
+                .unwrap()
+                .record_command(&self.plugin_id, command);
+        }
+
+        result
+    }
+}
+
+// Listing every fallible method keeps the match exhaustive as methods get added
+fn response_error(response: &ProtocolResponse) -> Option<String> {
+    macro_rules! error_of {
+        ($($method:ident),* $(,)?) => {
+            match response {
+                $(ProtocolResponse::$method(result) => result.as_ref().err().map(ToString::to_string),)*
+                ProtocolResponse::CurrentUser(_) => None,
+            }
+        };
+    }
+
+    error_of!(
+        SendMessage,
+        SendDm,
+        SendReply,
+        EditMessage,
+        DeleteMessage,
+        SendFile,
+        ContentSafe,
+        StartTyping,
+        StopTyping,
+        CreateGlobalCommand,
+        EditGlobalCommand,
+        UpsertGlobalCommand,
+        CreateGuildCommand,
+        EditGuildCommand,
+        UpsertGuildCommand,
+        ApplicationCommands,
+        GuildApplicationCommands,
+        CreateInteractionResponse,
+        EditInteractionResponse,
+        CreateReaction,
+        GetUser,
+        GetChannel,
+        ChannelWebhook,
+        ExecuteWebhook,
+        CreateThread,
+        CreateThreadFromMessage,
+        JoinThread,
+        LeaveThread,
+        ArchiveThread,
+        LockThread,
+        CreateForumPost,
+        CreatePoll,
+        EndPoll,
+        CreateScheduledEvent,
+        EditScheduledEvent,
+        DeleteScheduledEvent,
+        SetPresence,
+        KvGet,
+        KvSet,
+        KvDelete,
+        KvList,
+        ScheduleJob,
+        CancelJob,
+        ListJobs,
+        Publish,
+        CallPlugin,
+        RespondToCall
+    )
+}
+
+fn registered_command(response: &ProtocolResponse) -> Option<&Command> {
+    match response {
+        ProtocolResponse::CreateGlobalCommand(Ok(command))
+        | ProtocolResponse::EditGlobalCommand(Ok(command))
+        | ProtocolResponse::UpsertGlobalCommand(Ok(command))
+        | ProtocolResponse::CreateGuildCommand(Ok(command))
+        | ProtocolResponse::EditGuildCommand(Ok(command))
+        | ProtocolResponse::UpsertGuildCommand(Ok(command)) => Some(command),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+pub struct Server {
+    plugin_id: String,
     discord_http: Arc<DiscordHttp>,
     discord_cache: Arc<DiscordCache>,
+    storage: Storage,
+    scheduler: Scheduler,
+    publisher: Publisher,
+    plugin_calls: PluginCalls,
+    commands: CommandRegistry,
+    presence: PresenceControl,
+    webhooks: WebhookCache,
+
+    typings: TypingLeases,
+}
+
+// Typing indicators held for an RPC connection, serenity renews them until they're dropped
+#[derive(Debug, Clone, Default)]
+pub struct TypingLeases {
+    leases: Arc<parking_lot::Mutex<slotmap::SlotMap<TypingKey, Typing>>>,
+}
+
+const MAX_TYPING_LEASE: Duration = Duration::from_secs(10 * 60);
+
+impl TypingLeases {
+    fn start(&self, typing: Typing, duration: Duration) -> TypingKey {
+        let key = self.leases.lock().insert(typing);
+
+        let leases = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            leases.stop(key);
+        });
+
+        key
+    }
+
+    fn stop(&self, key: TypingKey) {
+        // Dropping the indicator ends its renewals
+        self.leases.lock().remove(key);
+    }
+
+    fn clear(&self) {
+        self.leases.lock().clear();
+    }
+}
 
-    typings: Arc<parking_lot::Mutex<slotmap::SlotMap<TypingKey, Typing>>>,
+impl Server {
+    // Indicators are otherwise kept up until their lease runs out
+    #[cfg(feature = "wasm")]
+    pub fn clear_typings(&self) {
+        self.typings.clear();
+    }
+
+    pub fn metered(self) -> Metered<ServeProtocol<Self>> {
+        Metered {
+            plugin_id: self.plugin_id.clone(),
+            inner: self.serve(),
+        }
+    }
 }
 
 impl Protocol for Server {
@@ -122,6 +605,17 @@ impl Protocol for Server {
         Ok(chan_id.send_message(self.discord_http, message).await?)
     }
 
+    async fn send_dm(
+        self,
+        _ctx: Context,
+        user_id: UserId,
+        content: String,
+    ) -> DiscordApiResult<Message> {
+        let channel = user_id.create_dm_channel(&self.discord_http).await?;
+        let message = CreateMessage::new().content(content);
+        Ok(channel.send_message(self.discord_http, message).await?)
+    }
+
     async fn send_reply(
         self,
         _ctx: Context,
@@ -171,20 +665,20 @@ impl Protocol for Server {
             .await?)
     }
 
-    async fn start_typing(self, _ctx: Context, chan_id: ChannelId) -> DiscordApiResult<TypingKey> {
+    async fn start_typing(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        max_duration: Duration,
+    ) -> DiscordApiResult<TypingKey> {
         let typing = self.discord_http.start_typing(chan_id);
-        let key = self.typings.lock().insert(typing);
-
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(8)).await;
-            self.typings.lock().remove(key);
-        });
-
-        Ok(key)
+        Ok(self
+            .typings
+            .start(typing, max_duration.min(MAX_TYPING_LEASE)))
     }
 
     async fn stop_typing(self, _ctx: Context, key: TypingKey) -> DiscordApiResult<()> {
-        let _ = self.typings.lock().remove(key);
+        self.typings.stop(key);
         Ok(())
     }
 
@@ -206,6 +700,7 @@ impl Protocol for Server {
         _ctx: Context,
         data: serde_json::Value,
     ) -> DiscordApiResult<Command> {
+        self.commands.remember(&self.plugin_id, None, &data);
         Ok(self.discord_http.create_global_command(&data).await?)
     }
 
@@ -215,10 +710,13 @@ impl Protocol for Server {
         command_id: CommandId,
         data: serde_json::Value,
     ) -> DiscordApiResult<Command> {
-        Ok(self
+        let command = self
             .discord_http
             .edit_global_command(command_id, &data)
-            .await?)
+            .await?;
+        self.commands
+            .remember_edit(&self.plugin_id, None, &command.name, &data);
+        Ok(command)
     }
 
     async fn upsert_global_command(
@@ -232,6 +730,7 @@ impl Protocol for Server {
             .as_str()
             .ok_or("Invalid command name")?;
 
+        self.commands.remember(&self.plugin_id, None, &cmd_data);
         let existing_commands = self.discord_http.get_global_commands().await?;
 
         let Some(existing_cmd) = existing_commands
@@ -255,6 +754,8 @@ impl Protocol for Server {
         guild_id: GuildId,
         data: serde_json::Value,
     ) -> DiscordApiResult<Command> {
+        self.commands
+            .remember(&self.plugin_id, Some(guild_id), &data);
         Ok(self
             .discord_http
             .create_guild_command(guild_id, &data)
@@ -268,10 +769,13 @@ impl Protocol for Server {
         guild_id: GuildId,
         data: serde_json::Value,
     ) -> DiscordApiResult<Command> {
-        Ok(self
+        let command = self
             .discord_http
             .edit_guild_command(guild_id, cmd_id, &data)
-            .await?)
+            .await?;
+        self.commands
+            .remember_edit(&self.plugin_id, Some(guild_id), &command.name, &data);
+        Ok(command)
     }
 
     async fn upsert_guild_command(
@@ -286,6 +790,8 @@ impl Protocol for Server {
             .as_str()
             .ok_or("Invalid command name")?;
 
+        self.commands
+            .remember(&self.plugin_id, Some(guild_id), &cmd_data);
         let existing_commands = self.discord_http.get_guild_commands(guild_id).await?;
 
         let Some(existing_cmd) = existing_commands
@@ -363,6 +869,305 @@ impl Protocol for Server {
     ) -> DiscordApiResult<DiscordChannel> {
         Ok(self.discord_http.get_channel(channel_id).await?)
     }
+
+    async fn channel_webhook(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+    ) -> DiscordApiResult<WebhookId> {
+        let bot_id = self.discord_cache.current_user().id;
+        let webhook = self
+            .webhooks
+            .get_or_create(&self.discord_http, bot_id, chan_id)
+            .await?;
+        Ok(webhook.id)
+    }
+
+    async fn execute_webhook(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        message: WebhookMessage,
+    ) -> DiscordApiResult<Message> {
+        let WebhookMessage {
+            content,
+            username,
+            avatar_url,
+            embeds,
+            files,
+            thread_id,
+        } = message;
+
+        let embeds = embeds
+            .into_iter()
+            .map(|embed| serde_json::from_value::<Embed>(embed).map(CreateEmbed::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|why| DiscordApiError(format!("Invalid embed: {why}")))?;
+        let files = files
+            .into_iter()
+            .map(|file| CreateAttachment::bytes(file.data, file.name));
+        let mut execute = ExecuteWebhook::new().embeds(embeds).add_files(files);
+        if !content.is_empty() {
+            execute = execute.content(content);
+        }
+        if let Some(username) = username {
+            execute = execute.username(username);
+        }
+        if let Some(avatar_url) = avatar_url {
+            execute = execute.avatar_url(avatar_url);
+        }
+        if let Some(thread_id) = thread_id {
+            execute = execute.in_thread(thread_id);
+        }
+
+        let bot_id = self.discord_cache.current_user().id;
+        let webhook = self
+            .webhooks
+            .get_or_create(&self.discord_http, bot_id, chan_id)
+            .await?;
+        match webhook.execute(&self.discord_http, true, execute).await {
+            Ok(message) => message.ok_or_else(|| "Discord did not return the message".into()),
+            Err(why) => {
+                self.webhooks.evict_if_gone(chan_id, &why);
+                Err(why.into())
+            }
+        }
+    }
+
+    async fn create_thread(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        name: String,
+        private: bool,
+    ) -> DiscordApiResult<GuildChannel> {
+        let kind = match private {
+            true => ChannelType::PrivateThread,
+            false => ChannelType::PublicThread,
+        };
+        let thread = CreateThread::new(name).kind(kind);
+        Ok(chan_id.create_thread(self.discord_http, thread).await?)
+    }
+
+    async fn create_thread_from_message(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        message_id: MessageId,
+        name: String,
+    ) -> DiscordApiResult<GuildChannel> {
+        let thread = CreateThread::new(name);
+        Ok(chan_id
+            .create_thread_from_message(self.discord_http, message_id, thread)
+            .await?)
+    }
+
+    async fn join_thread(self, _ctx: Context, thread_id: ChannelId) -> DiscordApiResult<()> {
+        Ok(thread_id.join_thread(self.discord_http).await?)
+    }
+
+    async fn leave_thread(self, _ctx: Context, thread_id: ChannelId) -> DiscordApiResult<()> {
+        Ok(thread_id.leave_thread(self.discord_http).await?)
+    }
+
+    async fn archive_thread(
+        self,
+        _ctx: Context,
+        thread_id: ChannelId,
+        archived: bool,
+    ) -> DiscordApiResult<GuildChannel> {
+        let edit = EditThread::new().archived(archived);
+        Ok(thread_id.edit_thread(self.discord_http, edit).await?)
+    }
+
+    async fn lock_thread(
+        self,
+        _ctx: Context,
+        thread_id: ChannelId,
+        locked: bool,
+    ) -> DiscordApiResult<GuildChannel> {
+        let edit = EditThread::new().locked(locked);
+        Ok(thread_id.edit_thread(self.discord_http, edit).await?)
+    }
+
+    async fn create_forum_post(
+        self,
+        _ctx: Context,
+        forum_id: ChannelId,
+        name: String,
+        content: String,
+        tags: Vec<ForumTagId>,
+    ) -> DiscordApiResult<GuildChannel> {
+        let post = CreateForumPost::new(name, CreateMessage::new().content(content))
+            .set_applied_tags(tags);
+        Ok(forum_id.create_forum_post(self.discord_http, post).await?)
+    }
+
+    async fn create_poll(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        poll: PollMessage,
+    ) -> DiscordApiResult<Message> {
+        let hours = poll_hours(poll.duration)?;
+        let answers = poll
+            .answers
+            .into_iter()
+            .map(|answer| CreatePollAnswer::new().text(answer))
+            .collect();
+        let mut create_poll = CreatePoll::new()
+            .question(poll.question)
+            .answers(answers)
+            .duration(Duration::from_secs(hours * 3600));
+        if poll.allow_multiselect {
+            create_poll = create_poll.allow_multiselect();
+        }
+
+        let message = CreateMessage::new().poll(create_poll);
+        Ok(chan_id.send_message(self.discord_http, message).await?)
+    }
+
+    async fn end_poll(
+        self,
+        _ctx: Context,
+        chan_id: ChannelId,
+        message_id: MessageId,
+    ) -> DiscordApiResult<Message> {
+        Ok(chan_id.end_poll(self.discord_http, message_id).await?)
+    }
+
+    async fn create_scheduled_event(
+        self,
+        _ctx: Context,
+        guild_id: GuildId,
+        data: serde_json::Value,
+    ) -> DiscordApiResult<ScheduledEvent> {
+        Ok(self
+            .discord_http
+            .create_scheduled_event(guild_id, &data, None)
+            .await?)
+    }
+
+    async fn edit_scheduled_event(
+        self,
+        _ctx: Context,
+        guild_id: GuildId,
+        event_id: ScheduledEventId,
+        data: serde_json::Value,
+    ) -> DiscordApiResult<ScheduledEvent> {
+        Ok(self
+            .discord_http
+            .edit_scheduled_event(guild_id, event_id, &data, None)
+            .await?)
+    }
+
+    async fn delete_scheduled_event(
+        self,
+        _ctx: Context,
+        guild_id: GuildId,
+        event_id: ScheduledEventId,
+    ) -> DiscordApiResult<()> {
+        Ok(guild_id
+            .delete_scheduled_event(self.discord_http, event_id)
+            .await?)
+    }
+
+    async fn set_presence(self, _ctx: Context, presence: Option<Presence>) -> DiscordApiResult<()> {
+        self.presence.set(&self.plugin_id, presence);
+        Ok(())
+    }
+
+    async fn kv_get(self, _ctx: Context, key: String) -> StorageResult<Option<serde_json::Value>> {
+        self.storage.get(self.plugin_id, key).await
+    }
+
+    async fn kv_set(
+        self,
+        _ctx: Context,
+        key: String,
+        value: serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> StorageResult<()> {
+        self.storage.set(self.plugin_id, key, value, ttl).await
+    }
+
+    async fn kv_delete(self, _ctx: Context, key: String) -> StorageResult<bool> {
+        self.storage.delete(self.plugin_id, key).await
+    }
+
+    async fn kv_list(
+        self,
+        _ctx: Context,
+        prefix: String,
+    ) -> StorageResult<Vec<(String, serde_json::Value)>> {
+        self.storage.list(self.plugin_id, prefix).await
+    }
+
+    async fn schedule_job(
+        self,
+        _ctx: Context,
+        schedule: JobSchedule,
+        payload: serde_json::Value,
+    ) -> SchedulerResult<ScheduledJob> {
+        self.scheduler
+            .schedule(self.plugin_id, schedule, payload)
+            .await
+    }
+
+    async fn cancel_job(self, _ctx: Context, job_id: JobId) -> SchedulerResult<bool> {
+        self.scheduler.cancel(self.plugin_id, job_id).await
+    }
+
+    async fn list_jobs(self, _ctx: Context) -> SchedulerResult<Vec<ScheduledJob>> {
+        Ok(self.scheduler.list(&self.plugin_id))
+    }
+
+    async fn publish(
+        self,
+        _ctx: Context,
+        topic: String,
+        payload: serde_json::Value,
+    ) -> PluginCallResult<usize> {
+        if topic.is_empty() {
+            return Err(PluginCallError("Topic can't be empty".to_owned()));
+        }
+
+        let subscribers = self.publisher.topic_subscribers(&topic);
+        self.publisher.broadcast(Event::Topic {
+            topic,
+            source: self.plugin_id,
+            payload,
+        });
+        Ok(subscribers)
+    }
+
+    async fn call_plugin(
+        self,
+        ctx: Context,
+        plugin_id: String,
+        method: String,
+        payload: serde_json::Value,
+    ) -> PluginCallResult<serde_json::Value> {
+        self.plugin_calls
+            .call(
+                &self.publisher,
+                self.plugin_id,
+                plugin_id,
+                method,
+                payload,
+                ctx.deadline,
+            )
+            .await
+    }
+
+    async fn respond_to_call(
+        self,
+        _ctx: Context,
+        call_id: CallId,
+        result: Result<serde_json::Value, String>,
+    ) -> PluginCallResult<()> {
+        self.plugin_calls.respond(&self.plugin_id, call_id, result)
+    }
 }
 
 fn command_changed(
@@ -392,3 +1197,39 @@ fn command_changed(
 
     Ok(description_changed || opts_changed)
 }
+
+// Discord counts whole hours, a poll shorter than an hour still lasts one
+fn poll_hours(duration: Duration) -> DiscordApiResult<u64> {
+    let hours = duration.as_secs().div_ceil(3600).max(1);
+    if hours > POLL_MAX_HOURS {
+        return Err(DiscordApiError(format!(
+            "Polls last at most {POLL_MAX_HOURS} hours, got {hours}"
+        )));
+    }
+    Ok(hours)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_durations_are_rounded_up_to_whole_hours() {
+        let hours = |secs| poll_hours(Duration::from_secs(secs)).unwrap();
+
+        assert_eq!(hours(0), 1);
+        assert_eq!(hours(60), 1);
+        assert_eq!(hours(3600), 1);
+        assert_eq!(hours(3601), 2);
+        assert_eq!(hours(24 * 3600), 24);
+    }
+
+    #[test]
+    fn polls_last_at_most_32_days() {
+        assert_eq!(
+            poll_hours(Duration::from_secs(POLL_MAX_HOURS * 3600)).unwrap(),
+            POLL_MAX_HOURS
+        );
+        assert!(poll_hours(Duration::from_secs(POLL_MAX_HOURS * 3600 + 1)).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, stream};
+use globibot_core::{
+    events::{Event, EventType},
+    rpc::{JobId, JobSchedule, ScheduledJob, SchedulerError, SchedulerResult, StorageResult},
+};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{events::Publisher, storage::Storage};
+
+type JobKey = (String, JobId);
+
+// Deliveries can each wait for `DELIVERY_TIMEOUT`, one slow plugin shouldn't hold up the others
+const MAX_CONCURRENT_DELIVERIES: usize = 16;
+
+#[derive(Clone)]
+pub struct Scheduler {
+    storage: Storage,
+    jobs: Arc<parking_lot::Mutex<HashMap<JobKey, ScheduledJob>>>,
+}
+
+impl Scheduler {
+    pub async fn load(storage: Storage) -> StorageResult<Self> {
+        let jobs = storage
+            .all_jobs()
+            .await?
+            .into_iter()
+            .map(|(owner, job)| ((owner, job.id), job))
+            .collect::<HashMap<_, _>>();
+
+        debug!("Loaded {} scheduled jobs", jobs.len());
+
+        Ok(Self {
+            storage,
+            jobs: Arc::new(parking_lot::Mutex::new(jobs)),
+        })
+    }
+
+    pub async fn schedule(
+        &self,
+        owner: String,
+        schedule: JobSchedule,
+        payload: Value,
+    ) -> SchedulerResult<ScheduledJob> {
+        let next_run = next_run(&schedule, SystemTime::now())?
+            .ok_or_else(|| SchedulerError("Schedule never fires".to_owned()))?;
+
+        let job = ScheduledJob {
+            id: JobId(rand::random()),
+            schedule,
+            next_run,
+            payload,
+        };
+
+        self.storage.save_job(owner.clone(), &job).await?;
+        self.jobs.lock().insert((owner, job.id), job.clone());
+
+        Ok(job)
+    }
+
+    pub async fn cancel(&self, owner: String, job_id: JobId) -> SchedulerResult<bool> {
+        let existed = self.jobs.lock().remove(&(owner.clone(), job_id)).is_some();
+        self.storage.remove_job(owner, job_id).await?;
+
+        Ok(existed)
+    }
+
+    pub fn list(&self, owner: &str) -> Vec<ScheduledJob> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .iter()
+            .filter(|((job_owner, _), _)| job_owner == owner)
+            .map(|(_, job)| job.clone())
+            .collect::<Vec<_>>();
+
+        jobs.sort_by_key(|job| job.next_run);
+        jobs
+    }
+
+    async fn run_due_jobs(&self, publisher: &Publisher) -> SchedulerResult<()> {
+        let now = SystemTime::now();
+
+        // Jobs stay due until their owner is around (and not paused) to receive them
+        let due_jobs = self
+            .jobs
+            .lock()
+            .iter()
+            .filter(|((owner, _), job)| {
+                job.next_run <= now && publisher.is_subscribed(owner, EventType::ScheduledJob)
+            })
+            .map(|(key, job)| (key.clone(), job.clone()))
+            .collect::<Vec<_>>();
+
+        let delivered = stream::iter(due_jobs)
+            .map(|((owner, job_id), job)| async move {
+                let delivered = publisher
+                    .deliver_to(&owner, Event::ScheduledJob { job: job.clone() })
+                    .await;
+                ((owner, job_id), job, delivered)
+            })
+            .buffer_unordered(MAX_CONCURRENT_DELIVERIES)
+            .collect::<Vec<_>>()
+            .await;
+
+        for ((owner, job_id), mut job, delivered) in delivered {
+            // Left due to be retried on the next tick
+            if !delivered {
+                warn!("Scheduled job {job_id:?} of '{owner}' was not delivered");
+                continue;
+            }
+
+            let next = match job.schedule {
+                JobSchedule::Cron(_) => next_run(&job.schedule, now)?,
+                JobSchedule::At(_) | JobSchedule::After(_) => None,
+            };
+
+            match next {
+                Some(next_run) => {
+                    job.next_run = next_run;
+                    // A job cancelled while it was being delivered stays cancelled
+                    if self.storage.reschedule_job(owner.clone(), &job).await?
+                        && let Some(scheduled) = self.jobs.lock().get_mut(&(owner, job_id))
+                    {
+                        *scheduled = job;
+                    }
+                }
+                None => {
+                    self.jobs.lock().remove(&(owner.clone(), job_id));
+                    self.storage.remove_job(owner, job_id).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run_scheduler(scheduler: Scheduler, publisher: Publisher) -> SchedulerResult<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+        if let Err(why) = scheduler.run_due_jobs(&publisher).await {
+            warn!("Failed to run scheduled jobs: {why}");
+        }
+    }
+}
+
+fn next_run(schedule: &JobSchedule, after: SystemTime) -> SchedulerResult<Option<SystemTime>> {
+    match schedule {
+        JobSchedule::At(at) => Ok(Some(*at)),
+        JobSchedule::After(delay) => Ok(Some(after + *delay)),
+        JobSchedule::Cron(expression) => {
+            let schedule = cron::Schedule::from_str(expression)
+                .map_err(|err| SchedulerError(format!("Invalid cron expression: {err}")))?;
+            let next = schedule.after(&DateTime::<Utc>::from(after)).next();
+            Ok(next.map(SystemTime::from))
+        }
+    }
+}
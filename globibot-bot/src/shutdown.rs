@@ -0,0 +1,84 @@
+use std::{io, time::Duration};
+
+use tokio::time::Instant;
+
+use globibot_core::{rpc::StorageResult, serenity::all::ShardManager};
+use serde_json::Value;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{info, warn};
+
+use crate::{
+    config,
+    events::Publisher,
+    rpc::RpcClients,
+    storage::{BOT_NAMESPACE, Storage},
+};
+
+const RESTART_TOKEN_KEY: &str = "restart_token";
+// How long plugins have to come back after a restart
+const RESTART_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+pub async fn wait_for_signal() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down..."),
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Received SIGINT, shutting down...");
+        }
+    }
+
+    Ok(())
+}
+
+// Token handed out by the previous process when it shut down
+pub async fn take_restart_token(storage: &Storage) -> StorageResult<Option<String>> {
+    let namespace = BOT_NAMESPACE.to_owned();
+    let key = RESTART_TOKEN_KEY.to_owned();
+
+    let token = storage.get(namespace.clone(), key.clone()).await?;
+    storage.delete(namespace, key).await?;
+
+    Ok(token.and_then(|token| token.as_str().map(str::to_owned)))
+}
+
+pub async fn run(
+    config: &config::Shutdown,
+    publisher: &Publisher,
+    rpc_clients: &RpcClients,
+    shard_manager: &ShardManager,
+    storage: &Storage,
+) -> StorageResult<()> {
+    let timeout = Duration::from_secs(config.drain_timeout_secs);
+    // Notifying plugins and draining RPC calls share the same timeout
+    let deadline = Instant::now() + timeout;
+
+    let restart_token = format!("{:032x}", rand::random::<u128>());
+    storage
+        .set(
+            BOT_NAMESPACE.to_owned(),
+            RESTART_TOKEN_KEY.to_owned(),
+            Value::String(restart_token.clone()),
+            Some(RESTART_TOKEN_TTL),
+        )
+        .await?;
+
+    info!("Notifying plugins");
+    if !publisher.shutdown(restart_token, timeout).await {
+        warn!("Some subscribers did not receive the shutdown event in time");
+    }
+
+    info!("Draining RPC clients");
+    if !rpc_clients
+        .drain(deadline.saturating_duration_since(Instant::now()))
+        .await
+    {
+        warn!("Some RPC requests were still being handled after {timeout:?}");
+    }
+
+    info!("Closing the gateway");
+    shard_manager.shutdown_all().await;
+
+    Ok(())
+}
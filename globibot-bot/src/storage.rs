@@ -0,0 +1,388 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use globibot_core::{
+    events::TracedEvent,
+    rpc::{JobId, ScheduledJob, StorageError, StorageResult},
+};
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+// Keys are namespaced by plugin ID
+const KV_TABLE: TableDefinition<(&str, &str), &[u8]> = TableDefinition::new("kv");
+const JOBS_TABLE: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("jobs");
+// Durable event queues, keyed by plugin ID and sequence number
+const EVENT_QUEUE_TABLE: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("event_queue");
+// Last sequence number handed out in each queue, so that numbers aren't reused once it empties
+const EVENT_SEQ_TABLE: TableDefinition<&str, u64> = TableDefinition::new("event_seq");
+
+// The bot's own keys, outside of any valid plugin ID
+pub const BOT_NAMESPACE: &str = "@globibot";
+
+// IDs starting with '@' belong to the bot, a plugin using one could read its namespace
+pub fn is_reserved_id(plugin_id: &str) -> bool {
+    plugin_id.starts_with('@')
+}
+
+#[derive(Clone)]
+pub struct Storage {
+    db: Arc<Database>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KvEntry {
+    value: Value,
+    expires_at: Option<SystemTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueuedEvent {
+    pub at: SystemTime,
+    pub event: TracedEvent,
+}
+
+impl KvEntry {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl Storage {
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let db = Database::create(path).map_err(storage_error)?;
+
+        let txn = db.begin_write().map_err(storage_error)?;
+        txn.open_table(KV_TABLE).map_err(storage_error)?;
+        txn.open_table(JOBS_TABLE).map_err(storage_error)?;
+        txn.open_table(EVENT_QUEUE_TABLE).map_err(storage_error)?;
+        txn.open_table(EVENT_SEQ_TABLE).map_err(storage_error)?;
+        txn.commit().map_err(storage_error)?;
+
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    pub async fn get(&self, namespace: String, key: String) -> StorageResult<Option<Value>> {
+        self.blocking(move |db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(KV_TABLE)?;
+
+            let entry = match table.get((namespace.as_str(), key.as_str()))? {
+                Some(data) => decode(data.value())?,
+                None => return Ok(None),
+            };
+
+            if entry.is_expired(SystemTime::now()) {
+                return Ok(None);
+            }
+
+            Ok(Some(entry.value))
+        })
+        .await
+    }
+
+    pub async fn set(
+        &self,
+        namespace: String,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StorageResult<()> {
+        let entry = KvEntry {
+            value,
+            expires_at: ttl.map(|ttl| SystemTime::now() + ttl),
+        };
+        let data = serde_json::to_vec(&entry).map_err(storage_error)?;
+
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            {
+                let mut table = txn.open_table(KV_TABLE)?;
+                table.insert((namespace.as_str(), key.as_str()), data.as_slice())?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn delete(&self, namespace: String, key: String) -> StorageResult<bool> {
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            let existed = {
+                let mut table = txn.open_table(KV_TABLE)?;
+                let removed = table.remove((namespace.as_str(), key.as_str()))?;
+                match removed {
+                    Some(data) => !decode(data.value())?.is_expired(SystemTime::now()),
+                    None => false,
+                }
+            };
+            txn.commit()?;
+            Ok(existed)
+        })
+        .await
+    }
+
+    pub async fn list(
+        &self,
+        namespace: String,
+        prefix: String,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        self.blocking(move |db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(KV_TABLE)?;
+            let now = SystemTime::now();
+
+            let mut entries = vec![];
+            for item in table.range((namespace.as_str(), prefix.as_str())..)? {
+                let (key, data) = item?;
+                let (key_namespace, key) = key.value();
+                if key_namespace != namespace || !key.starts_with(&prefix) {
+                    break;
+                }
+
+                let entry = decode(data.value())?;
+                if !entry.is_expired(now) {
+                    entries.push((key.to_owned(), entry.value));
+                }
+            }
+
+            Ok(entries)
+        })
+        .await
+    }
+
+    pub async fn purge_expired(&self) -> StorageResult<()> {
+        self.blocking(|db| {
+            let now = SystemTime::now();
+            let txn = db.begin_write()?;
+            {
+                let mut table = txn.open_table(KV_TABLE)?;
+                table.retain(|_key, data| {
+                    decode(data).map_or(true, |entry| !entry.is_expired(now))
+                })?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn save_job(&self, namespace: String, job: &ScheduledJob) -> StorageResult<()> {
+        let JobId(job_id) = job.id;
+        let data = serde_json::to_vec(job).map_err(storage_error)?;
+
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            {
+                let mut table = txn.open_table(JOBS_TABLE)?;
+                table.insert((namespace.as_str(), job_id), data.as_slice())?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    // Only updates jobs that weren't removed in the meantime, returns whether it did
+    pub async fn reschedule_job(
+        &self,
+        namespace: String,
+        job: &ScheduledJob,
+    ) -> StorageResult<bool> {
+        let JobId(job_id) = job.id;
+        let data = serde_json::to_vec(job).map_err(storage_error)?;
+
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            let exists = {
+                let mut table = txn.open_table(JOBS_TABLE)?;
+                let key = (namespace.as_str(), job_id);
+                let exists = table.get(key)?.is_some();
+                if exists {
+                    table.insert(key, data.as_slice())?;
+                }
+                exists
+            };
+            txn.commit()?;
+            Ok(exists)
+        })
+        .await
+    }
+
+    pub async fn remove_job(&self, namespace: String, JobId(job_id): JobId) -> StorageResult<bool> {
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            let existed = {
+                let mut table = txn.open_table(JOBS_TABLE)?;
+                table.remove((namespace.as_str(), job_id))?.is_some()
+            };
+            txn.commit()?;
+            Ok(existed)
+        })
+        .await
+    }
+
+    pub async fn all_jobs(&self) -> StorageResult<Vec<(String, ScheduledJob)>> {
+        self.blocking(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(JOBS_TABLE)?;
+
+            let mut jobs = vec![];
+            for item in table.iter()? {
+                let (key, data) = item?;
+                let (namespace, _job_id) = key.value();
+                let job = serde_json::from_slice(data.value())
+                    .map_err(|err| redb::Error::Corrupted(err.to_string()))?;
+                jobs.push((namespace.to_owned(), job));
+            }
+
+            Ok(jobs)
+        })
+        .await
+    }
+
+    // Appends to the plugin's queue, dropping its oldest events beyond `max_len`
+    pub async fn push_event(
+        &self,
+        namespace: String,
+        event: &QueuedEvent,
+        max_len: u64,
+    ) -> StorageResult<u64> {
+        let data = serde_json::to_vec(event).map_err(storage_error)?;
+
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            let seq = {
+                let mut table = txn.open_table(EVENT_QUEUE_TABLE)?;
+                let mut sequences = txn.open_table(EVENT_SEQ_TABLE)?;
+                let namespace = namespace.as_str();
+                let last_seq = sequences.get(namespace)?.map(|seq| seq.value());
+                // Queues written before sequence numbers were kept carry on from their last event
+                let last_queued = table
+                    .range((namespace, 0)..=(namespace, u64::MAX))?
+                    .next_back()
+                    .transpose()?
+                    .map(|(key, _)| key.value().1);
+                let seq = last_seq.max(last_queued).map_or(1, |last| last + 1);
+                sequences.insert(namespace, seq)?;
+                table.insert((namespace, seq), data.as_slice())?;
+                if let Some(oldest_kept) = seq.checked_sub(max_len) {
+                    table.retain_in((namespace, 0)..=(namespace, oldest_kept), |_, _| false)?;
+                }
+                seq
+            };
+            txn.commit()?;
+            Ok(seq)
+        })
+        .await
+    }
+
+    pub async fn queued_events(
+        &self,
+        namespace: String,
+        from: u64,
+        limit: usize,
+    ) -> StorageResult<Vec<(u64, QueuedEvent)>> {
+        self.blocking(move |db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(EVENT_QUEUE_TABLE)?;
+            let namespace = namespace.as_str();
+
+            let mut events = vec![];
+            for item in table
+                .range((namespace, from)..=(namespace, u64::MAX))?
+                .take(limit)
+            {
+                let (key, data) = item?;
+                let event = serde_json::from_slice(data.value())
+                    .map_err(|err| redb::Error::Corrupted(err.to_string()))?;
+                events.push((key.value().1, event));
+            }
+
+            Ok(events)
+        })
+        .await
+    }
+
+    pub async fn ack_event(&self, namespace: String, seq: u64) -> StorageResult<bool> {
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            let existed = {
+                let mut table = txn.open_table(EVENT_QUEUE_TABLE)?;
+                table.remove((namespace.as_str(), seq))?.is_some()
+            };
+            txn.commit()?;
+            Ok(existed)
+        })
+        .await
+    }
+
+    pub async fn purge_queued_events(&self, older_than: SystemTime) -> StorageResult<()> {
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            {
+                let mut table = txn.open_table(EVENT_QUEUE_TABLE)?;
+                table.retain(|_key, data| {
+                    serde_json::from_slice::<QueuedEvent>(data)
+                        .map_or(true, |queued| queued.at >= older_than)
+                })?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn blocking<T, F>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&Database) -> Result<T, DbError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(storage_error)?
+            .map_err(storage_error)
+    }
+}
+
+pub async fn run_expiry(storage: Storage) -> StorageResult<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        match storage.purge_expired().await {
+            Ok(()) => debug!("Purged expired storage entries"),
+            Err(why) => warn!("Failed to purge expired storage entries: {why}"),
+        }
+    }
+}
+
+// Boxed since `redb::Error` is rather large
+#[derive(Debug)]
+struct DbError(Box<redb::Error>);
+
+impl<E: Into<redb::Error>> From<E> for DbError {
+    fn from(err: E) -> Self {
+        Self(Box::new(err.into()))
+    }
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+fn decode(data: &[u8]) -> Result<KvEntry, DbError> {
+    serde_json::from_slice(data).map_err(|err| redb::Error::Corrupted(err.to_string()).into())
+}
+
+fn storage_error(err: impl ToString) -> StorageError {
+    StorageError(err.to_string())
+}
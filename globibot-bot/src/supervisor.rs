@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use globibot_core::transport::Address;
+use serde::Serialize;
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::watch,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, PluginPolicies, Telemetry},
+    web::WEB_STATE,
+};
+
+const LOG_HISTORY: usize = 200;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// A plugin running for that long is considered healthy again and restarts without delay
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    plugins: Arc<parking_lot::Mutex<HashMap<String, SupervisedPlugin>>>,
+    // Shared with the listeners so that enabled plugins are let in once they connect
+    policies: PluginPolicies,
+}
+
+#[derive(Debug)]
+struct SupervisedPlugin {
+    enabled: watch::Sender<bool>,
+    state: ProcessState,
+    restarts: u32,
+    logs: VecDeque<LogLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ProcessState {
+    Disabled,
+    Starting,
+    Running {
+        pid: Option<u32>,
+        since: SystemTime,
+    },
+    Restarting {
+        exit_status: Option<String>,
+        after: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    at: SystemTime,
+    stream: LogStream,
+    line: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupervisedPluginStatus {
+    pub id: String,
+    pub enabled: bool,
+    pub connected: bool,
+    pub restarts: u32,
+    #[serde(flatten)]
+    pub state: ProcessState,
+}
+
+pub struct Spawner {
+    plugin_id: String,
+    enabled: bool,
+    command: PathBuf,
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    subscriber_addr: Address,
+    rpc_addr: Address,
+    config_path: PathBuf,
+    // Removed along with the plugins' config files once the spawners are gone
+    _config_dir: Arc<TempDir>,
+    restart_token: Option<String>,
+    // Plugins get as long as the bot drains to exit by themselves once it shuts down
+    shutdown_grace: Duration,
+}
+
+pub fn spawners(config: &Config, restart_token: Option<&str>) -> io::Result<Vec<Spawner>> {
+    if !config.supervisor.enabled {
+        return Ok(vec![]);
+    }
+    let config_dir = Arc::new(tempfile::Builder::new().prefix("globibot-").tempdir()?);
+
+    let subscriber_addr = config
+        .supervisor
+        .subscriber_addr
+        .clone()
+        .unwrap_or_else(|| connectable(&config.listeners.subscriber_addr));
+    let rpc_addr = config
+        .supervisor
+        .rpc_addr
+        .clone()
+        .unwrap_or_else(|| connectable(&config.listeners.rpc_addr));
+
+    let mut spawners = vec![];
+    for (id, plugin) in &config.plugins {
+        let Some(command) = plugin.command.clone() else {
+            continue;
+        };
+        let config_path =
+            write_plugin_config(config_dir.path(), id, &plugin.settings, &config.telemetry)?;
+        spawners.push(Spawner {
+            plugin_id: id.clone(),
+            enabled: plugin.policy.enabled,
+            command,
+            args: plugin.args.clone(),
+            env: plugin.env.clone(),
+            subscriber_addr: subscriber_addr.clone(),
+            rpc_addr: rpc_addr.clone(),
+            config_path,
+            _config_dir: Arc::clone(&config_dir),
+            restart_token: restart_token.map(str::to_owned),
+            shutdown_grace: Duration::from_secs(config.shutdown.drain_timeout_secs),
+        });
+    }
+
+    Ok(spawners)
+}
+
+// Plugins only get their own section and the telemetry settings, none of the bot's secrets
+fn write_plugin_config(
+    dir: &Path,
+    plugin_id: &str,
+    settings: &toml::Table,
+    telemetry: &Telemetry,
+) -> io::Result<PathBuf> {
+    let mut config = toml::Table::new();
+    let plugins = toml::Table::from_iter([(plugin_id.to_owned(), settings.clone().into())]);
+    config.insert("plugins".to_owned(), plugins.into());
+    if let Some(endpoint) = &telemetry.otlp_endpoint {
+        let telemetry =
+            toml::Table::from_iter([("otlp_endpoint".to_owned(), endpoint.as_str().into())]);
+        config.insert("telemetry".to_owned(), telemetry.into());
+    }
+
+    let path = dir.join(format!("{plugin_id}.toml"));
+    let content = toml::to_string(&config).map_err(io::Error::other)?;
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+pub async fn run_supervisor(
+    supervisor: Supervisor,
+    spawners: Vec<Spawner>,
+    shutting_down: CancellationToken,
+) {
+    let tasks = spawners.into_iter().map(|spawner| {
+        let enabled = supervisor.register(&spawner.plugin_id, spawner.enabled);
+        supervisor
+            .clone()
+            .supervise(spawner, enabled, shutting_down.clone())
+    });
+
+    futures::future::join_all(tasks).await;
+}
+
+impl Supervisor {
+    pub fn new(policies: PluginPolicies) -> Self {
+        Self {
+            plugins: <_>::default(),
+            policies,
+        }
+    }
+
+    fn register(&self, plugin_id: &str, enabled: bool) -> watch::Receiver<bool> {
+        let (tx, rx) = watch::channel(enabled);
+        self.plugins.lock().insert(
+            plugin_id.to_owned(),
+            SupervisedPlugin {
+                enabled: tx,
+                state: ProcessState::Disabled,
+                restarts: 0,
+                logs: VecDeque::with_capacity(LOG_HISTORY),
+            },
+        );
+        rx
+    }
+
+    async fn supervise(
+        self,
+        spawner: Spawner,
+        mut enabled: watch::Receiver<bool>,
+        shutting_down: CancellationToken,
+    ) {
+        let plugin_id = spawner.plugin_id.as_str();
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            if !*enabled.borrow() {
+                self.set_state(plugin_id, ProcessState::Disabled);
+            }
+            tokio::select! {
+                biased;
+                _ = shutting_down.cancelled() => return,
+                result = enabled.wait_for(|enabled| *enabled) => {
+                    if result.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            self.set_state(plugin_id, ProcessState::Starting);
+            let started_at = Instant::now();
+            let exit_status = match spawner.spawn() {
+                Ok(child) => {
+                    info!("Started plugin '{plugin_id}'");
+                    let watched = self.watch_child(
+                        plugin_id,
+                        child,
+                        &mut enabled,
+                        &shutting_down,
+                        spawner.shutdown_grace,
+                    );
+                    match watched.await {
+                        Some(exit_status) => exit_status,
+                        None => {
+                            info!("Stopped plugin '{plugin_id}'");
+                            continue;
+                        }
+                    }
+                }
+                Err(why) => {
+                    warn!("Failed to start plugin '{plugin_id}': {why}");
+                    None
+                }
+            };
+
+            if started_at.elapsed() >= STABLE_AFTER {
+                backoff = MIN_BACKOFF;
+            }
+
+            warn!(
+                "Plugin '{plugin_id}' exited ({status}), restarting in {backoff:?}",
+                status = describe(exit_status)
+            );
+            self.set_state(
+                plugin_id,
+                ProcessState::Restarting {
+                    exit_status: exit_status.map(|status| status.to_string()),
+                    after: backoff,
+                },
+            );
+            if let Some(plugin) = self.plugins.lock().get_mut(plugin_id) {
+                plugin.restarts += 1;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = enabled.wait_for(|enabled| !*enabled) => {}
+                _ = shutting_down.cancelled() => return,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    // Returns `None` when the plugin got disabled while running or the bot is shutting down
+    async fn watch_child(
+        &self,
+        plugin_id: &str,
+        mut child: Child,
+        enabled: &mut watch::Receiver<bool>,
+        shutting_down: &CancellationToken,
+        shutdown_grace: Duration,
+    ) -> Option<Option<ExitStatus>> {
+        self.set_state(
+            plugin_id,
+            ProcessState::Running {
+                pid: child.id(),
+                since: SystemTime::now(),
+            },
+        );
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(
+                self.clone()
+                    .capture(plugin_id.to_owned(), LogStream::Stdout, stdout),
+            );
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(
+                self.clone()
+                    .capture(plugin_id.to_owned(), LogStream::Stderr, stderr),
+            );
+        }
+
+        tokio::select! {
+            status = child.wait() => Some(status.ok()),
+            _ = enabled.wait_for(|enabled| !*enabled) => {
+                if let Err(why) = child.kill().await {
+                    warn!("Failed to kill plugin '{plugin_id}': {why}");
+                }
+                None
+            }
+            // The plugin is sent the shutdown event and should exit on its own
+            _ = shutting_down.cancelled() => {
+                if tokio::time::timeout(shutdown_grace, child.wait()).await.is_err()
+                    && let Err(why) = child.kill().await
+                {
+                    warn!("Failed to kill plugin '{plugin_id}': {why}");
+                }
+                None
+            }
+        }
+    }
+
+    async fn capture(self, plugin_id: String, stream: LogStream, output: impl AsyncRead + Unpin) {
+        let mut lines = BufReader::new(output).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            info!(target: "globibot_bot::plugin", "[{plugin_id}] {line}");
+
+            if let Some(plugin) = self.plugins.lock().get_mut(&plugin_id) {
+                if plugin.logs.len() == LOG_HISTORY {
+                    plugin.logs.pop_front();
+                }
+                plugin.logs.push_back(LogLine {
+                    at: SystemTime::now(),
+                    stream,
+                    line,
+                });
+            }
+        }
+    }
+
+    fn set_state(&self, plugin_id: &str, state: ProcessState) {
+        let status = {
+            let mut plugins = self.plugins.lock();
+            let Some(plugin) = plugins.get_mut(plugin_id) else {
+                return;
+            };
+            plugin.state = state;
+            status(plugin_id, plugin)
+        };
+
+        WEB_STATE.lock().unwrap().update_supervised_plugin(status);
+    }
+
+    pub fn set_enabled(&self, plugin_id: &str, enabled: bool) -> bool {
+        match self.plugins.lock().get(plugin_id) {
+            Some(plugin) => {
+                self.policies.set_enabled(plugin_id, enabled);
+                plugin.enabled.send_replace(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<SupervisedPluginStatus> {
+        let mut statuses = self
+            .plugins
+            .lock()
+            .iter()
+            .map(|(id, plugin)| status(id, plugin))
+            .collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.id.cmp(&b.id));
+        statuses
+    }
+
+    pub fn logs(&self, plugin_id: &str) -> Option<Vec<LogLine>> {
+        self.plugins
+            .lock()
+            .get(plugin_id)
+            .map(|plugin| plugin.logs.iter().cloned().collect())
+    }
+}
+
+impl Spawner {
+    fn spawn(&self) -> std::io::Result<Child> {
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .envs(&self.env)
+            .env("SUBSCRIBER_ADDR", self.subscriber_addr.to_string())
+            .env("RPC_ADDR", self.rpc_addr.to_string())
+            .env("GLOBIBOT_CONFIG", &self.config_path)
+            .env_remove("DISCORD_TOKEN")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(restart_token) = &self.restart_token {
+            command.env("RESTART_TOKEN", restart_token);
+        }
+
+        command.spawn()
+    }
+}
+
+fn status(plugin_id: &str, plugin: &SupervisedPlugin) -> SupervisedPluginStatus {
+    SupervisedPluginStatus {
+        id: plugin_id.to_owned(),
+        enabled: *plugin.enabled.borrow(),
+        connected: WEB_STATE.lock().unwrap().is_connected(plugin_id),
+        restarts: plugin.restarts,
+        state: plugin.state.clone(),
+    }
+}
+
+fn describe(exit_status: Option<ExitStatus>) -> String {
+    match exit_status {
+        Some(status) => status.to_string(),
+        None => "failed to run".to_owned(),
+    }
+}
+
+// Listeners usually bind every interface, which plugins can't connect to as is
+fn connectable(address: &Address) -> Address {
+    match address {
+        Address::Tcp(addr) => {
+            let addr = match addr.rsplit_once(':') {
+                Some(("0.0.0.0", port)) => format!("127.0.0.1:{port}"),
+                Some(("[::]", port)) => format!("[::1]:{port}"),
+                _ => addr.clone(),
+            };
+            Address::Tcp(addr)
+        }
+        Address::Unix(path) => Address::Unix(path.clone()),
+    }
+}
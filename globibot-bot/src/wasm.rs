@@ -0,0 +1,308 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use futures::{StreamExt, channel::mpsc};
+use globibot_core::{
+    events::{Event, EventType, HandshakeRequest, TracedEvent},
+    rpc::{self, ProtocolRequest},
+    trace::set_remote_parent,
+};
+use serde_json::Value;
+use tarpc::server::Serve;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
+use wasmtime::{
+    Engine, Store, StoreLimits, StoreLimitsBuilder,
+    component::{Component, HasSelf, Linker},
+};
+
+use crate::{
+    config::{Config, PluginPolicies, WasmPlugin},
+    events::spawn_subscriber,
+    rpc::{Server, Services},
+    web::{RpcEndpoint, WEB_STATE},
+};
+
+wasmtime::component::bindgen!({
+    path: "wit",
+    world: "plugin",
+    imports: { default: async },
+    exports: { default: async },
+});
+
+use globibot::plugin::host::{self, Level};
+
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// Lets other tasks run while a guest burns through its fuel
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+
+pub struct WasmHost {
+    plugin_id: String,
+    spec: WasmPlugin,
+}
+
+struct HostState {
+    plugin_id: String,
+    server: Option<Server>,
+    limits: StoreLimits,
+}
+
+struct LoadedPlugin {
+    modified: SystemTime,
+    component: Component,
+    instance: Option<(Store<HostState>, Plugin)>,
+}
+
+pub fn hosts(config: &Config) -> Vec<WasmHost> {
+    config
+        .plugins
+        .iter()
+        .filter_map(|(id, plugin)| {
+            Some(WasmHost {
+                plugin_id: id.clone(),
+                spec: plugin.wasm.clone()?,
+            })
+        })
+        .collect()
+}
+
+pub async fn run_hosts(
+    hosts: Vec<WasmHost>,
+    services: Services,
+    policies: PluginPolicies,
+) -> wasmtime::Result<()> {
+    if hosts.is_empty() {
+        return Ok(());
+    }
+
+    let mut config = wasmtime::Config::new();
+    config
+        .async_support(true)
+        .consume_fuel(true)
+        .wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+
+    let mut linker = Linker::<HostState>::new(&engine);
+    Plugin::add_to_linker::<_, HasSelf<_>>(&mut linker, |state| state)?;
+
+    let tasks = hosts.into_iter().map(|host| {
+        host.run(
+            engine.clone(),
+            linker.clone(),
+            services.clone(),
+            policies.clone(),
+        )
+    });
+    futures::future::join_all(tasks).await;
+
+    Ok(())
+}
+
+impl WasmHost {
+    async fn run(
+        self,
+        engine: Engine,
+        linker: Linker<HostState>,
+        services: Services,
+        policies: PluginPolicies,
+    ) {
+        let plugin_id = self.plugin_id.as_str();
+        let policy = policies.get(plugin_id);
+        if !policy.enabled {
+            info!("WASM plugin '{plugin_id}' is disabled");
+            return;
+        }
+
+        // Shared by every instance, so that typing indicators can be cleared on reload
+        let server = policy.rpc.then(|| services.server(plugin_id.to_owned()));
+        let new_store = || {
+            let state = HostState {
+                plugin_id: plugin_id.to_owned(),
+                server: server.clone(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.spec.max_memory)
+                    .build(),
+            };
+            let mut store = Store::new(&engine, state);
+            store.limiter(|state| &mut state.limits);
+            store
+        };
+
+        let (_, mut events) = mpsc::channel::<TracedEvent>(0);
+        let mut loaded: Option<LoadedPlugin> = None;
+        let mut subscriber: Option<CancellationToken> = None;
+        let mut reload_check = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = reload_check.tick() => {
+                    let modified = match modified_at(&self.spec.path) {
+                        Ok(modified) => modified,
+                        Err(why) => {
+                            warn!("Cannot read WASM plugin '{plugin_id}': {why}");
+                            continue;
+                        }
+                    };
+                    if loaded.as_ref().is_some_and(|loaded| loaded.modified == modified) {
+                        continue;
+                    }
+
+                    match self.load(&engine, &linker, new_store(), modified).await {
+                        Ok((plugin, request)) => {
+                            // The previous version must not get any more events nor keep typing
+                            if let Some(previous) = subscriber.take() {
+                                previous.cancel();
+                            }
+                            if let Some(server) = &server {
+                                server.clear_typings();
+                            }
+                            let (sender, receiver) = mpsc::channel(16);
+                            events = receiver;
+                            subscriber = spawn_subscriber(
+                                &services.publisher,
+                                &policies,
+                                request,
+                                None,
+                                sender,
+                            );
+                            if policy.rpc {
+                                WEB_STATE.lock().unwrap().register_plugin_rpc(
+                                    plugin_id,
+                                    globibot_core::PROTOCOL_VERSION,
+                                    RpcEndpoint {
+                                        instance: None,
+                                        connected_at: SystemTime::now(),
+                                        remote_addr: None,
+                                    },
+                                );
+                            }
+                            info!("Loaded WASM plugin '{plugin_id}'");
+                            loaded = Some(plugin);
+                        }
+                        Err(why) => error!("Failed to load WASM plugin '{plugin_id}': {why:#}"),
+                    }
+                }
+                Some(TracedEvent { event, trace, .. }) = events.next() => {
+                    let Some(loaded) = &mut loaded else {
+                        continue;
+                    };
+                    let span = info_span!("event", plugin = plugin_id, event_type = ?event.ty());
+                    if let Some(parent) = &trace {
+                        set_remote_parent(&span, parent);
+                    }
+                    let handle_event = loaded
+                        .handle_event(&linker, &new_store, event, &self.spec)
+                        .instrument(span);
+                    if let Err(why) = handle_event.await {
+                        warn!("WASM plugin '{plugin_id}' failed to handle event: {why:#}");
+                    }
+                }
+            }
+        }
+    }
+
+    async fn load(
+        &self,
+        engine: &Engine,
+        linker: &Linker<HostState>,
+        mut store: Store<HostState>,
+        modified: SystemTime,
+    ) -> wasmtime::Result<(LoadedPlugin, HandshakeRequest)> {
+        let component = Component::from_file(engine, &self.spec.path)?;
+        let plugin = Plugin::instantiate_async(&mut store, &component, linker).await?;
+
+        store.set_fuel(self.spec.fuel)?;
+        let subscription = plugin.call_subscription(&mut store).await?;
+
+        let events = subscription
+            .events
+            .into_iter()
+            .map(|name| serde_json::from_value::<EventType>(Value::String(name)))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let request = HandshakeRequest {
+            id: self.plugin_id.clone(),
+            events,
+            topics: subscription.topics.into_iter().collect(),
+            protocol_version: globibot_core::PROTOCOL_VERSION,
+            restart_token: None,
+            group: None,
+        };
+
+        let loaded = LoadedPlugin {
+            modified,
+            component,
+            instance: Some((store, plugin)),
+        };
+        Ok((loaded, request))
+    }
+}
+
+impl LoadedPlugin {
+    async fn handle_event(
+        &mut self,
+        linker: &Linker<HostState>,
+        new_store: &impl Fn() -> Store<HostState>,
+        event: Event,
+        spec: &WasmPlugin,
+    ) -> wasmtime::Result<()> {
+        let (mut store, plugin) = match self.instance.take() {
+            Some(instance) => instance,
+            None => {
+                debug!("Instantiating again after a trap");
+                let mut store = new_store();
+                let plugin = Plugin::instantiate_async(&mut store, &self.component, linker).await?;
+                (store, plugin)
+            }
+        };
+
+        store.set_fuel(spec.fuel)?;
+        store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))?;
+
+        let event = serde_json::to_string(&event)?;
+        // A trapped instance can't be entered again, it is only kept when the call succeeds
+        let result = plugin.call_on_event(&mut store, &event).await?;
+        self.instance = Some((store, plugin));
+
+        result.map_err(wasmtime::Error::msg)
+    }
+}
+
+impl host::Host for HostState {
+    async fn call(&mut self, request: String) -> Result<String, String> {
+        let Some(server) = &self.server else {
+            return Err(format!(
+                "Plugin '{}' is not allowed to use RPC",
+                self.plugin_id
+            ));
+        };
+
+        let request =
+            serde_json::from_str::<ProtocolRequest>(&request).map_err(|e| e.to_string())?;
+        let response = server
+            .clone()
+            .metered()
+            .serve(rpc::context::current(), request)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        serde_json::to_string(&response).map_err(|e| e.to_string())
+    }
+
+    async fn log(&mut self, level: Level, message: String) {
+        let plugin_id = &self.plugin_id;
+        match level {
+            Level::Trace => trace!("[{plugin_id}] {message}"),
+            Level::Debug => debug!("[{plugin_id}] {message}"),
+            Level::Info => info!("[{plugin_id}] {message}"),
+            Level::Warn => warn!("[{plugin_id}] {message}"),
+            Level::Error => error!("[{plugin_id}] {message}"),
+        }
+    }
+}
+
+fn modified_at(path: &Path) -> std::io::Result<SystemTime> {
+    std::fs::metadata(path)?.modified()
+}
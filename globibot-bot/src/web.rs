@@ -1,28 +1,72 @@
 use std::{
-    collections::HashMap,
-    sync::{LazyLock, Mutex},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
 };
 
 use axum::{
     BoxError, Json, Router,
-    extract::State,
+    extract::{FromRef, Path, State},
+    http::StatusCode,
     response::{Sse, sse::Event},
     routing::get,
 };
 use futures::{Stream, TryStreamExt};
+use globibot_core::{
+    events::{ConsumerGroup, EventType},
+    serenity::all::{Command, CommandId, GuildId},
+};
 use tokio::sync::broadcast::Receiver;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    admin::{self, Admin},
+    metrics,
+    supervisor::{SupervisedPluginStatus, Supervisor},
+};
 
-pub async fn run_server() -> std::io::Result<()> {
-    let app = Router::new() //
+pub async fn run_server(
+    addr: String,
+    supervisor: Supervisor,
+    admin: Option<Admin>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let mut app = Router::new() //
         .route("/", get(async || "Globibot Web Server"))
         .route("/plugins", get(list_plugins))
+        .route("/plugins/{id}", get(plugin_details))
+        .route("/shards", get(list_shards))
+        .route("/supervisor", get(list_supervised_plugins))
         .route("/sse", get(stream_events))
-        .with_state(SseMessageReceiver {
+        .route("/metrics", get(render_metrics));
+
+    match admin {
+        Some(admin) => app = app.nest("/admin", admin::routes(admin)),
+        None => tracing::info!("No admin tokens configured, admin endpoints are disabled"),
+    }
+
+    let app = app.with_state(AppState {
+        sse: SseMessageReceiver {
             rx: WEB_STATE.lock().unwrap().tx.subscribe(),
-        });
+        },
+        supervisor,
+    });
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8001").await?;
-    axum::serve(listener, app).await?;
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(STATS_UPDATE_INTERVAL);
+        loop {
+            interval.tick().await;
+            WEB_STATE.lock().unwrap().send_stats_updates();
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
 
     Ok(())
 }
@@ -33,12 +77,44 @@ async fn list_plugins() -> Json<Vec<ConnectedPlugin>> {
         .unwrap()
         .plugins
         .values()
-        .cloned()
+        .map(PluginDetails::summary)
         .collect();
     Json(plugins)
 }
 
-#[axum::debug_handler]
+async fn plugin_details(Path(id): Path<String>) -> Result<Json<PluginDetails>, StatusCode> {
+    let state = WEB_STATE.lock().unwrap();
+    let plugin = state.plugins.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(plugin.with_stats()))
+}
+
+async fn render_metrics() -> String {
+    {
+        let state = WEB_STATE.lock().unwrap();
+        let count =
+            |has: fn(&PluginDetails) -> bool| state.plugins.values().filter(|p| has(p)).count();
+        metrics::CONNECTED_PLUGINS
+            .with_label_values(&["rpc"])
+            .set(count(|plugin| !plugin.rpc.is_empty()) as i64);
+        metrics::CONNECTED_PLUGINS
+            .with_label_values(&["events"])
+            .set(count(|plugin| !plugin.events.is_empty()) as i64);
+    }
+
+    metrics::render()
+}
+
+async fn list_shards() -> Json<Vec<ShardStatus>> {
+    Json(WEB_STATE.lock().unwrap().shards.values().cloned().collect())
+}
+
+async fn list_supervised_plugins(
+    State(supervisor): State<Supervisor>,
+) -> Json<Vec<SupervisedPluginStatus>> {
+    Json(supervisor.statuses())
+}
+
+#[axum::debug_handler(state = AppState)]
 async fn stream_events(
     State(SseMessageReceiver { rx }): State<SseMessageReceiver>,
 ) -> Sse<impl Stream<Item = Result<Event, BoxError>>> {
@@ -54,6 +130,9 @@ async fn stream_events(
 enum SseMessage {
     UpsertedPlugin(ConnectedPlugin),
     RemovedPlugin(String),
+    PluginDetails(Box<PluginDetails>),
+    SupervisorUpdate(SupervisedPluginStatus),
+    ShardUpdate(ShardStatus),
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -63,6 +142,115 @@ struct ConnectedPlugin {
     has_events: bool,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PluginDetails {
+    name: String,
+    protocol_version: Option<u32>,
+    // One entry per connected instance of the plugin
+    rpc: Vec<RpcEndpoint>,
+    events: Vec<EventsEndpoint>,
+    events_delivered: u64,
+    events_dropped: u64,
+    rpc_calls: BTreeMap<String, u64>,
+    last_errors: VecDeque<RpcErrorRecord>,
+    commands: BTreeMap<CommandId, RegisteredCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ShardStatus {
+    pub id: u32,
+    pub stage: String,
+    pub latency: Option<Duration>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RpcEndpoint {
+    // None for plugins running inside the bot
+    pub instance: Option<u64>,
+    pub connected_at: SystemTime,
+    pub remote_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EventsEndpoint {
+    pub instance: u64,
+    pub group: Option<ConsumerGroup>,
+    pub connected_at: SystemTime,
+    pub remote_addr: Option<String>,
+    pub events: HashSet<EventType>,
+    pub topics: HashSet<String>,
+    // Requested events the plugin's policy doesn't allow
+    pub denied_events: HashSet<EventType>,
+    pub muted_events: HashSet<EventType>,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RpcErrorRecord {
+    at: SystemTime,
+    method: String,
+    error: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RegisteredCommand {
+    name: String,
+    guild_id: Option<GuildId>,
+}
+
+impl PluginDetails {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            protocol_version: None,
+            rpc: Vec::new(),
+            events: Vec::new(),
+            events_delivered: 0,
+            events_dropped: 0,
+            rpc_calls: BTreeMap::new(),
+            last_errors: VecDeque::with_capacity(RPC_ERROR_HISTORY),
+            commands: BTreeMap::new(),
+        }
+    }
+
+    // Copies in the current counters, which are kept apart in `PLUGIN_STATS`
+    fn with_stats(&self) -> Self {
+        let mut details = self.clone();
+        if let Some(stats) = PLUGIN_STATS.read().get(&self.name) {
+            details.events_delivered = stats.events_delivered.load(Ordering::Relaxed);
+            details.events_dropped = stats.events_dropped.load(Ordering::Relaxed);
+            details.rpc_calls = stats.rpc_calls.lock().clone();
+        }
+        details
+    }
+
+    fn summary(&self) -> ConnectedPlugin {
+        ConnectedPlugin {
+            name: self.name.clone(),
+            has_rpc: !self.rpc.is_empty(),
+            has_events: !self.events.is_empty(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    sse: SseMessageReceiver,
+    supervisor: Supervisor,
+}
+
+impl FromRef<AppState> for SseMessageReceiver {
+    fn from_ref(state: &AppState) -> Self {
+        state.sse.clone()
+    }
+}
+
+impl FromRef<AppState> for Supervisor {
+    fn from_ref(state: &AppState) -> Self {
+        state.supervisor.clone()
+    }
+}
+
 struct SseMessageReceiver {
     rx: Receiver<SseMessage>,
 }
@@ -75,48 +263,232 @@ impl Clone for SseMessageReceiver {
     }
 }
 
+const RPC_ERROR_HISTORY: usize = 20;
+// Counters change with every event and call, their updates are batched
+const STATS_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
 pub static WEB_STATE: LazyLock<Mutex<WebServerState>> = LazyLock::new(|| {
     Mutex::new(WebServerState {
         plugins: HashMap::new(),
+        shards: BTreeMap::new(),
         tx: tokio::sync::broadcast::channel(1 << 8).0,
     })
 });
 
+// Identifies connections of both the RPC server and the event publisher in the dashboard
+pub fn next_instance() -> u64 {
+    static NEXT_INSTANCE: AtomicU64 = AtomicU64::new(0);
+    NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed)
+}
+
+// Bumped with every event and call, so kept out of `WEB_STATE` to not contend on it
+static PLUGIN_STATS: LazyLock<parking_lot::RwLock<HashMap<String, Arc<PluginStats>>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Default)]
+struct PluginStats {
+    events_delivered: AtomicU64,
+    events_dropped: AtomicU64,
+    rpc_calls: parking_lot::Mutex<BTreeMap<String, u64>>,
+    // Set when the counters changed since they were last sent to dashboards
+    stale: AtomicBool,
+}
+
+fn update_stats(name: &str, update: impl FnOnce(&PluginStats)) {
+    if let Some(stats) = PLUGIN_STATS.read().get(name) {
+        update(stats);
+        stats.stale.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn record_events_delivered(name: &str) {
+    update_stats(name, |stats| {
+        stats.events_delivered.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+pub fn record_events_dropped(name: &str, count: u64) {
+    update_stats(name, |stats| {
+        stats.events_dropped.fetch_add(count, Ordering::Relaxed);
+    });
+}
+
+pub fn record_rpc_call(name: &str, method: &str) {
+    update_stats(name, |stats| {
+        *stats.rpc_calls.lock().entry(method.to_owned()).or_default() += 1;
+    });
+}
+
 #[derive(Debug)]
 pub struct WebServerState {
-    plugins: HashMap<String, ConnectedPlugin>,
+    plugins: HashMap<String, PluginDetails>,
+    shards: BTreeMap<u32, ShardStatus>,
     tx: tokio::sync::broadcast::Sender<SseMessage>,
 }
 
 impl WebServerState {
-    pub fn register_plugin_rpc(&mut self, name: &str) {
+    pub fn register_plugin_rpc(&mut self, name: &str, protocol_version: u32, rpc: RpcEndpoint) {
         let plugin = self.get_or_create_plugin(name);
-        plugin.has_rpc = true;
+        plugin.protocol_version = Some(protocol_version);
+        plugin
+            .rpc
+            .retain(|endpoint| endpoint.instance != rpc.instance);
+        plugin.rpc.push(rpc);
+
+        self.send_upserted(name);
+    }
+
+    pub fn register_plugin_events(
+        &mut self,
+        name: &str,
+        protocol_version: u32,
+        events: EventsEndpoint,
+    ) {
+        let plugin = self.get_or_create_plugin(name);
+        plugin.protocol_version = Some(protocol_version);
+        plugin.events.push(events);
+
+        self.send_upserted(name);
+    }
+
+    pub fn update_event_filters(
+        &mut self,
+        name: &str,
+        paused: bool,
+        muted_events: HashSet<EventType>,
+    ) {
+        let Some(plugin) = self.plugins.get_mut(name) else {
+            return;
+        };
+        for events in &mut plugin.events {
+            events.paused = paused;
+            events.muted_events = muted_events.clone();
+        }
+
+        self.send_details(name);
+    }
+
+    pub fn record_rpc_error(&mut self, name: &str, method: &str, error: String) {
+        let Some(plugin) = self.plugins.get_mut(name) else {
+            return;
+        };
+        if plugin.last_errors.len() == RPC_ERROR_HISTORY {
+            plugin.last_errors.pop_front();
+        }
+        plugin.last_errors.push_back(RpcErrorRecord {
+            at: SystemTime::now(),
+            method: method.to_owned(),
+            error,
+        });
 
-        let plugin = plugin.clone();
-        self.tx.send(SseMessage::UpsertedPlugin(plugin)).ok();
+        self.send_details(name);
     }
 
-    pub fn register_plugin_events(&mut self, name: &str) {
-        let existing_plugin = self.get_or_create_plugin(name);
-        existing_plugin.has_events = true;
+    pub fn record_command(&mut self, name: &str, command: &Command) {
+        let Some(plugin) = self.plugins.get_mut(name) else {
+            return;
+        };
+        plugin.commands.insert(
+            command.id,
+            RegisteredCommand {
+                name: command.name.clone(),
+                guild_id: command.guild_id,
+            },
+        );
 
-        let plugin = existing_plugin.clone();
-        self.tx.send(SseMessage::UpsertedPlugin(plugin)).ok();
+        self.send_details(name);
     }
 
-    fn get_or_create_plugin(&mut self, name: &str) -> &mut ConnectedPlugin {
+    fn send_upserted(&mut self, name: &str) {
+        if let Some(plugin) = self.plugins.get(name) {
+            self.tx
+                .send(SseMessage::UpsertedPlugin(plugin.summary()))
+                .ok();
+        }
+        self.send_details(name);
+    }
+
+    fn send_details(&mut self, name: &str) {
+        if let Some(stats) = PLUGIN_STATS.read().get(name) {
+            stats.stale.store(false, Ordering::Relaxed);
+        }
+        if let Some(plugin) = self.plugins.get(name) {
+            self.tx
+                .send(SseMessage::PluginDetails(Box::new(plugin.with_stats())))
+                .ok();
+        }
+    }
+
+    fn send_stats_updates(&mut self) {
+        let stale: Vec<_> = PLUGIN_STATS
+            .read()
+            .iter()
+            .filter(|(_, stats)| stats.stale.load(Ordering::Relaxed))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in stale {
+            self.send_details(&name);
+        }
+    }
+
+    fn get_or_create_plugin(&mut self, name: &str) -> &mut PluginDetails {
+        PLUGIN_STATS.write().entry(name.to_owned()).or_default();
         self.plugins
             .entry(name.to_string())
-            .or_insert_with(|| ConnectedPlugin {
-                name: name.to_string(),
-                has_rpc: false,
-                has_events: false,
-            })
+            .or_insert_with(|| PluginDetails::new(name))
+    }
+
+    pub fn is_connected(&self, name: &str) -> bool {
+        self.plugins.contains_key(name)
+    }
+
+    pub fn update_shard(&mut self, status: ShardStatus) {
+        if self.shards.get(&status.id) == Some(&status) {
+            return;
+        }
+        self.shards.insert(status.id, status.clone());
+        self.tx.send(SseMessage::ShardUpdate(status)).ok();
+    }
+
+    // Stage changes are reported as they happen, latencies only when shards are polled
+    pub fn update_shard_stage(&mut self, id: u32, stage: String) {
+        let latency = self.shards.get(&id).and_then(|shard| shard.latency);
+        self.update_shard(ShardStatus { id, stage, latency });
+    }
+
+    pub fn update_supervised_plugin(&mut self, status: SupervisedPluginStatus) {
+        self.tx.send(SseMessage::SupervisorUpdate(status)).ok();
+    }
+
+    pub fn remove_plugin_rpc(&mut self, name: &str, instance: Option<u64>) {
+        if let Some(plugin) = self.plugins.get_mut(name) {
+            plugin.rpc.retain(|endpoint| endpoint.instance != instance);
+            self.remove_instance(name);
+        }
+    }
+
+    pub fn remove_plugin_events(&mut self, name: &str, instance: u64) {
+        if let Some(plugin) = self.plugins.get_mut(name) {
+            plugin
+                .events
+                .retain(|endpoint| endpoint.instance != instance);
+            self.remove_instance(name);
+        }
+    }
+
+    fn remove_instance(&mut self, name: &str) {
+        match self.plugins.get(name) {
+            Some(plugin) if plugin.rpc.is_empty() && plugin.events.is_empty() => {
+                self.remove_plugin(name);
+            }
+            Some(_) => self.send_upserted(name),
+            None => {}
+        }
     }
 
-    pub fn remove_plugin(&mut self, name: &str) {
+    fn remove_plugin(&mut self, name: &str) {
         self.plugins.remove(name);
+        PLUGIN_STATS.write().remove(name);
         self.tx
             .send(SseMessage::RemovedPlugin(name.to_string()))
             .ok();
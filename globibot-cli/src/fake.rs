@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use globibot_core::{
+    rpc::{self, DiscordApiError, DiscordApiResult, ProtocolRequest, ProtocolResponse},
+    serenity::model::{
+        Timestamp,
+        application::Command,
+        channel::Message,
+        id::{ChannelId, GuildId},
+        user::{CurrentUser, User},
+    },
+};
+use parking_lot::Mutex;
+use serde_json::{Value, json};
+use tarpc::ServerError;
+
+use crate::Args;
+
+pub struct FakeDiscord {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub user: User,
+    pub direct: bool,
+    bot: User,
+    next_id: AtomicU64,
+    commands: Mutex<HashMap<String, Command>>,
+    kv: Mutex<HashMap<String, Value>>,
+}
+
+impl FakeDiscord {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            guild_id: args.guild_id.into(),
+            channel_id: args.channel_id.into(),
+            user: fake_user(args.user_id, "cli-user", false),
+            direct: args.direct,
+            bot: fake_user(args.bot_id, "globibot", true),
+            next_id: AtomicU64::new(1 << 32),
+            commands: <_>::default(),
+            kv: <_>::default(),
+        }
+    }
+
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn command(&self, name: &str) -> Option<Command> {
+        self.commands.lock().get(name).cloned()
+    }
+
+    pub fn respond(&self, request: ProtocolRequest) -> Result<ProtocolResponse, ServerError> {
+        use ProtocolRequest as Req;
+        use ProtocolResponse as Resp;
+
+        let response = match request {
+            Req::CurrentUser {} => {
+                let current_user = serde_json::from_value::<CurrentUser>(json!(self.bot));
+                Resp::CurrentUser(current_user.map_err(invalid_data)?)
+            }
+            Req::SendMessage { chan_id, content } => {
+                Resp::SendMessage(Ok(self.message(chan_id, content)))
+            }
+            Req::SendDm { content, .. } => {
+                let mut message = self.message(self.channel_id, content);
+                message.guild_id = None;
+                Resp::SendDm(Ok(message))
+            }
+            Req::SendReply {
+                chan_id, content, ..
+            } => Resp::SendReply(Ok(self.message(chan_id, content))),
+            Req::SendFile { chan_id, name, .. } => {
+                Resp::SendFile(Ok(self.message(chan_id, format!("<file: {name}>"))))
+            }
+            Req::EditMessage {
+                mut message,
+                new_content,
+            } => {
+                message.content = new_content;
+                Resp::EditMessage(Ok(message))
+            }
+            Req::EditInteractionResponse { data, .. } => {
+                let content = data["content"].as_str().unwrap_or_default().to_owned();
+                Resp::EditInteractionResponse(Ok(self.message(self.channel_id, content)))
+            }
+            Req::ContentSafe { content, .. } => Resp::ContentSafe(Ok(content)),
+            Req::SetPresence { .. } => Resp::SetPresence(Ok(())),
+            Req::Publish { .. } => Resp::Publish(Ok(0)),
+            Req::GetUser { user_id } => {
+                let user = fake_user(user_id.get(), &format!("user-{user_id}"), false);
+                Resp::GetUser(Ok(user))
+            }
+            Req::CreateGlobalCommand { data } => Resp::CreateGlobalCommand(self.register(data)),
+            Req::UpsertGlobalCommand { data } => Resp::UpsertGlobalCommand(self.register(data)),
+            Req::EditGlobalCommand { data, .. } => Resp::EditGlobalCommand(self.register(data)),
+            Req::CreateGuildCommand { data, .. } => Resp::CreateGuildCommand(self.register(data)),
+            Req::UpsertGuildCommand { data, .. } => Resp::UpsertGuildCommand(self.register(data)),
+            Req::EditGuildCommand { data, .. } => Resp::EditGuildCommand(self.register(data)),
+            Req::ApplicationCommands {} => {
+                Resp::ApplicationCommands(Ok(self.commands.lock().values().cloned().collect()))
+            }
+            Req::KvGet { key } => Resp::KvGet(Ok(self.kv.lock().get(&key).cloned())),
+            Req::KvSet { key, value, .. } => {
+                self.kv.lock().insert(key, value);
+                Resp::KvSet(Ok(()))
+            }
+            Req::KvDelete { key } => Resp::KvDelete(Ok(self.kv.lock().remove(&key).is_some())),
+            Req::KvList { prefix } => {
+                let kv = self.kv.lock();
+                let entries = kv.iter().filter(|(key, _)| key.starts_with(&prefix));
+                Resp::KvList(Ok(entries.map(|(k, v)| (k.clone(), v.clone())).collect()))
+            }
+            request => unit_or_unsupported(&request).map_err(invalid_data)?,
+        };
+
+        Ok(response)
+    }
+
+    fn message(&self, channel_id: ChannelId, content: String) -> Message {
+        let mut message = Message::default();
+        message.id = self.next_id().into();
+        message.channel_id = channel_id;
+        message.guild_id = Some(self.guild_id);
+        message.author = self.bot.clone();
+        message.content = content;
+        message.timestamp = Timestamp::now();
+        message
+    }
+
+    fn register(&self, data: Value) -> DiscordApiResult<Command> {
+        let name = data["name"].as_str().ok_or("Missing command name")?;
+
+        let mut commands = self.commands.lock();
+        let id = match commands.get(name) {
+            Some(existing) => existing.id.get(),
+            None => self.next_id(),
+        };
+
+        let mut command_data = json!({
+            "id": id.to_string(),
+            "type": 1,
+            "application_id": self.bot.id.to_string(),
+            "version": id.to_string(),
+        });
+        if let (Some(command_data), Some(data)) = (command_data.as_object_mut(), data.as_object()) {
+            for (key, value) in data {
+                command_data.entry(key).or_insert_with(|| value.clone());
+            }
+        }
+
+        let command = serde_json::from_value::<Command>(command_data)
+            .map_err(|err| DiscordApiError(err.to_string()))?;
+        commands.insert(command.name.clone(), command.clone());
+
+        Ok(command)
+    }
+}
+
+pub fn fake_user(id: u64, name: &str, bot: bool) -> User {
+    let mut user = User::default();
+    user.id = id.into();
+    user.name = name.to_owned();
+    user.bot = bot;
+    user
+}
+
+// Methods without a dedicated fake either return `()`, in which case they trivially succeed, or
+// return some Discord model we cannot make up, in which case they fail
+fn unit_or_unsupported(request: &ProtocolRequest) -> serde_json::Result<ProtocolResponse> {
+    rpc::response_for(request, json!({ "Ok": null }))
+        .or_else(|_| rpc::response_for(request, json!({ "Err": "Not supported by globibot-cli" })))
+}
+
+fn invalid_data(err: serde_json::Error) -> ServerError {
+    ServerError::new(io::ErrorKind::InvalidData, err.to_string())
+}
@@ -0,0 +1,130 @@
+use anyhow::Context;
+use globibot_core::{
+    events::Event,
+    serenity::{
+        model::{
+            Timestamp,
+            application::{CommandOption, CommandOptionType},
+            channel::Message,
+        },
+        utils::{parse_channel_mention, parse_user_mention},
+    },
+};
+use serde_json::{Value, json};
+
+use crate::fake::{FakeDiscord, fake_user};
+
+// `/name [subcommand…] [option=value…]` invokes a registered slash command, anything else is sent
+// as a plain message
+pub fn parse_event(line: &str, discord: &FakeDiscord) -> anyhow::Result<Event> {
+    match line.trim().strip_prefix('/') {
+        Some(command) => interaction_event(command, discord),
+        None => Ok(message_event(line, discord)),
+    }
+}
+
+fn message_event(content: &str, discord: &FakeDiscord) -> Event {
+    let mut message = Message::default();
+    message.id = discord.next_id().into();
+    message.channel_id = discord.channel_id;
+    message.guild_id = (!discord.direct).then_some(discord.guild_id);
+    message.author = discord.user.clone();
+    message.content = content.to_owned();
+    message.timestamp = Timestamp::now();
+    message.mentions = content
+        .split_whitespace()
+        .filter_map(parse_user_mention)
+        .map(|user_id| fake_user(user_id.get(), &format!("user-{user_id}"), false))
+        .collect();
+
+    let message = Box::new(message);
+    match discord.direct {
+        true => Event::DirectMessageCreate { message },
+        false => Event::MessageCreate { message },
+    }
+}
+
+fn interaction_event(command: &str, discord: &FakeDiscord) -> anyhow::Result<Event> {
+    let mut words = command.split_whitespace();
+    let name = words.next().context("Missing command name")?;
+    let command = discord
+        .command(name)
+        .with_context(|| format!("Unknown command '{name}', the plugin did not register it"))?;
+
+    let mut schema = command.options.as_slice();
+    let mut subcommands = vec![];
+    let mut options = vec![];
+
+    for word in words {
+        match word.split_once('=') {
+            Some((name, value)) => options.push(option_value(schema, name, value)?),
+            None if options.is_empty() => {
+                let subcommand = find_option(schema, word)?;
+                subcommands.push((word, subcommand.kind));
+                schema = &subcommand.options;
+            }
+            None => anyhow::bail!("Subcommand '{word}' must come before options"),
+        }
+    }
+
+    for (name, kind) in subcommands.into_iter().rev() {
+        options = vec![json!({ "name": name, "type": u8::from(kind), "options": options })];
+    }
+
+    let interaction = json!({
+        "id": discord.next_id().to_string(),
+        "application_id": command.application_id.to_string(),
+        "type": 2,
+        "data": {
+            "id": command.id.to_string(),
+            "name": command.name,
+            "type": 1,
+            "options": options,
+        },
+        "guild_id": discord.guild_id.to_string(),
+        "channel_id": discord.channel_id.to_string(),
+        "user": discord.user,
+        "token": format!("cli-token-{}", discord.next_id()),
+        "version": 1,
+        "app_permissions": null,
+        "locale": "en-US",
+        "guild_locale": null,
+        "entitlements": [],
+        "context": null,
+        "attachment_size_limit": 8 << 20,
+    });
+
+    Ok(Event::InteractionCreate {
+        interaction: Box::new(serde_json::from_value(interaction)?),
+    })
+}
+
+fn find_option<'a>(schema: &'a [CommandOption], name: &str) -> anyhow::Result<&'a CommandOption> {
+    schema
+        .iter()
+        .find(|opt| opt.name == name)
+        .with_context(|| format!("Unknown option '{name}'"))
+}
+
+fn option_value(schema: &[CommandOption], name: &str, value: &str) -> anyhow::Result<Value> {
+    let kind = find_option(schema, name)?.kind;
+
+    let value = match kind {
+        CommandOptionType::Integer => json!(value.parse::<i64>()?),
+        CommandOptionType::Number => json!(value.parse::<f64>()?),
+        CommandOptionType::Boolean => json!(value.parse::<bool>()?),
+        CommandOptionType::User | CommandOptionType::Mentionable => {
+            let user_id =
+                parse_user_mention(value).map_or_else(|| value.parse(), |id| Ok(id.get()));
+            json!(user_id?.to_string())
+        }
+        CommandOptionType::Channel => {
+            let chan_id =
+                parse_channel_mention(value).map_or_else(|| value.parse(), |id| Ok(id.get()));
+            json!(chan_id?.to_string())
+        }
+        _ => json!(value),
+    };
+
+    Ok(json!({ "name": name, "type": u8::from(kind), "value": value }))
+}
@@ -0,0 +1,163 @@
+mod fake;
+mod input;
+
+use std::sync::Arc;
+
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use globibot_core::{
+    events::{self, EventWrite, HandshakeRequest},
+    rpc::{self, ProtocolRequest},
+    transport::{Address, Protocol},
+};
+use tarpc::server::Channel;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tracing::warn;
+
+use fake::FakeDiscord;
+
+/// Impersonates the bot for a single plugin: lines typed on stdin become events, and every RPC
+/// call made by the plugin is printed
+#[derive(Debug, Parser)]
+struct Args {
+    /// Address to accept the plugin's event subscription on, `tcp://host:port` or `unix:///path`
+    #[arg(long, env = "SUBSCRIBER_ADDR")]
+    subscriber_addr: Address,
+
+    /// Address to accept the plugin's RPC connection on
+    #[arg(long, env = "RPC_ADDR")]
+    rpc_addr: Address,
+
+    /// Guild the injected events originate from
+    #[arg(long, default_value_t = 1000)]
+    guild_id: u64,
+
+    /// Channel the injected events originate from
+    #[arg(long, default_value_t = 1001)]
+    channel_id: u64,
+
+    /// User authoring the injected events
+    #[arg(long, default_value_t = 1002)]
+    user_id: u64,
+
+    /// User ID reported for the bot through `current_user`
+    #[arg(long, default_value_t = 1003)]
+    bot_id: u64,
+
+    /// Send plain lines as direct messages to the bot instead of guild messages
+    #[arg(long)]
+    direct: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+    let discord = Arc::new(FakeDiscord::new(&args));
+
+    futures::try_join!(
+        run_events(args.subscriber_addr, Arc::clone(&discord)),
+        run_rpc(args.rpc_addr, discord),
+    )?;
+
+    Ok(())
+}
+
+async fn run_events<P>(protocol: P, discord: Arc<FakeDiscord>) -> anyhow::Result<()>
+where
+    P: Protocol,
+    P::Client: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut subscribers = std::pin::pin!(protocol.listen().await?);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut subscriber: Option<(HandshakeRequest, EventWrite<P::Client>)> = None;
+
+    println!("Waiting for a plugin to connect…");
+
+    loop {
+        tokio::select! {
+            transport = subscribers.next() => {
+                let Some(transport) = transport.transpose()? else {
+                    return Ok(());
+                };
+                match events::accept(transport).await {
+                    Ok((request, sink)) => {
+                        println!(
+                            "Plugin '{}' subscribed to {:?}",
+                            request.id, request.events
+                        );
+                        subscriber = Some((request, sink));
+                    }
+                    Err(why) => warn!("Failed to accept subscriber: {why}"),
+                }
+            }
+
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let event = match input::parse_event(&line, &discord) {
+                    Ok(event) => event,
+                    Err(why) => {
+                        println!("Invalid input: {why}");
+                        continue;
+                    }
+                };
+
+                let Some((request, sink)) = subscriber.as_mut() else {
+                    println!("No plugin connected yet, dropping event");
+                    continue;
+                };
+                if !request.events.contains(&event.ty()) {
+                    println!("Plugin '{}' is not subscribed to {:?}", request.id, event.ty());
+                    continue;
+                }
+                if let Err(why) = sink.send(event.into()).await {
+                    println!("Plugin '{}' disconnected: {why}", request.id);
+                    subscriber = None;
+                }
+            }
+        }
+    }
+}
+
+async fn run_rpc<P>(protocol: P, discord: Arc<FakeDiscord>) -> anyhow::Result<()>
+where
+    P: Protocol,
+    P::Client: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut clients = std::pin::pin!(protocol.listen().await?);
+
+    while let Some(transport) = clients.next().await.transpose()? {
+        match rpc::accept(Default::default(), transport).await {
+            Ok((request, client)) => {
+                println!("Plugin '{}' connected to RPC", request.id);
+
+                let discord = Arc::clone(&discord);
+                let serve = tarpc::server::serve(move |_ctx, request: ProtocolRequest| {
+                    let discord = Arc::clone(&discord);
+                    async move {
+                        println!("→ {request:?}");
+                        let response = discord.respond(request);
+                        if let Ok(response) = &response {
+                            println!("← {response:?}");
+                        }
+                        response
+                    }
+                });
+
+                tokio::spawn(client.execute(serve).for_each(|response| async {
+                    tokio::spawn(response);
+                }));
+            }
+            Err(why) => warn!("Failed to accept RPC client: {why}"),
+        }
+    }
+
+    Ok(())
+}
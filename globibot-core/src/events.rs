@@ -1,12 +1,26 @@
-use crate::transport::{FramedRead, FramedWrite, frame_transport};
+use crate::{
+    PROTOCOL_VERSION,
+    rpc::{CallId, ScheduledJob},
+    trace::TraceContext,
+    transport::{FramedRead, FramedStream, FramedWrite, frame_transport},
+};
 
-use std::{collections::HashSet, io, time::Duration};
+use std::{
+    collections::HashSet,
+    io,
+    time::{Duration, SystemTime},
+};
 
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serenity::model::{
     application::CommandInteraction,
-    channel::Message,
+    channel::{GuildChannel, Message, PartialGuildChannel},
+    event::{
+        GuildScheduledEventUserAddEvent, GuildScheduledEventUserRemoveEvent,
+        MessagePollVoteAddEvent, MessagePollVoteRemoveEvent,
+    },
     id::{ChannelId, MessageId},
 };
 use tokio::{
@@ -19,6 +33,11 @@ pub enum Event {
     MessageCreate {
         message: Box<Message>,
     },
+    // Messages sent to the bot in private, kept apart so that policies can deny them. They are
+    // never sent as `MessageCreate`, plugins handling DMs need to subscribe to this event
+    DirectMessageCreate {
+        message: Box<Message>,
+    },
     MessageDelete {
         channel_id: ChannelId,
         message_id: MessageId,
@@ -26,29 +45,172 @@ pub enum Event {
     InteractionCreate {
         interaction: Box<CommandInteraction>,
     },
+    ThreadCreate {
+        thread: Box<GuildChannel>,
+    },
+    ThreadUpdate {
+        thread: Box<GuildChannel>,
+    },
+    ThreadDelete {
+        thread: PartialGuildChannel,
+    },
+    PollVoteAdd {
+        vote: MessagePollVoteAddEvent,
+    },
+    PollVoteRemove {
+        vote: MessagePollVoteRemoveEvent,
+    },
+    // Users marking themselves as interested in a guild scheduled event, or not anymore
+    ScheduledEventUserAdd {
+        subscription: GuildScheduledEventUserAddEvent,
+    },
+    ScheduledEventUserRemove {
+        subscription: GuildScheduledEventUserRemoveEvent,
+    },
+    ScheduledJob {
+        job: ScheduledJob,
+    },
+    Topic {
+        topic: String,
+        source: String,
+        payload: Value,
+    },
+    PluginCall {
+        call_id: CallId,
+        source: String,
+        method: String,
+        payload: Value,
+    },
+    // Sent to every subscriber when the bot stops, the token lets them resume after a restart
+    Shutdown {
+        restart_token: String,
+    },
 }
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum EventType {
     MessageCreate,
+    DirectMessageCreate,
     MessageDelete,
     InteractionCreate,
+    ThreadCreate,
+    ThreadUpdate,
+    ThreadDelete,
+    PollVoteAdd,
+    PollVoteRemove,
+    ScheduledEventUserAdd,
+    ScheduledEventUserRemove,
+    ScheduledJob,
+    Topic,
+    PluginCall,
+    Shutdown,
 }
 
 impl Event {
     pub fn ty(&self) -> EventType {
         match self {
             Event::MessageCreate { .. } => EventType::MessageCreate,
+            Event::DirectMessageCreate { .. } => EventType::DirectMessageCreate,
             Event::MessageDelete { .. } => EventType::MessageDelete,
             Event::InteractionCreate { .. } => EventType::InteractionCreate,
+            Event::ThreadCreate { .. } => EventType::ThreadCreate,
+            Event::ThreadUpdate { .. } => EventType::ThreadUpdate,
+            Event::ThreadDelete { .. } => EventType::ThreadDelete,
+            Event::PollVoteAdd { .. } => EventType::PollVoteAdd,
+            Event::PollVoteRemove { .. } => EventType::PollVoteRemove,
+            Event::ScheduledEventUserAdd { .. } => EventType::ScheduledEventUserAdd,
+            Event::ScheduledEventUserRemove { .. } => EventType::ScheduledEventUserRemove,
+            Event::ScheduledJob { .. } => EventType::ScheduledJob,
+            Event::Topic { .. } => EventType::Topic,
+            Event::PluginCall { .. } => EventType::PluginCall,
+            Event::Shutdown { .. } => EventType::Shutdown,
+        }
+    }
+
+    pub fn channel_id(&self) -> Option<ChannelId> {
+        match self {
+            Event::MessageCreate { message } | Event::DirectMessageCreate { message } => {
+                Some(message.channel_id)
+            }
+            Event::MessageDelete { channel_id, .. } => Some(*channel_id),
+            Event::InteractionCreate { interaction } => Some(interaction.channel_id),
+            // Messages posted in a thread carry the thread's ID as their channel
+            Event::ThreadCreate { thread } | Event::ThreadUpdate { thread } => Some(thread.id),
+            Event::ThreadDelete { thread } => Some(thread.id),
+            Event::PollVoteAdd { vote } => Some(vote.channel_id),
+            Event::PollVoteRemove { vote } => Some(vote.channel_id),
+            _ => None,
         }
     }
 }
 
+// What actually goes over the wire, the trace links a plugin's spans to the bot's
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TracedEvent {
+    pub event: Event,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    // Gateway shard the event was received on, only set for events coming from Discord
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<u32>,
+    // Set when the event comes from a durable queue, it's redelivered until acknowledged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack_id: Option<u64>,
+}
+
+impl From<Event> for TracedEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            event,
+            trace: None,
+            shard: None,
+            ack_id: None,
+        }
+    }
+}
+
+// Sent back by subscribers once they processed a queued event
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EventAck {
+    pub ack_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub at: SystemTime,
+    pub event: Event,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeRequest {
     pub id: String,
     pub events: HashSet<EventType>,
+    #[serde(default)]
+    pub topics: HashSet<String>,
+    #[serde(default)]
+    pub protocol_version: u32,
+    // Replays the events missed while the bot was restarting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_token: Option<String>,
+    // Instances in the same group share the stream instead of each getting every event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<ConsumerGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsumerGroup {
+    pub id: String,
+    #[serde(default)]
+    pub balance: Balance,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    // Keeps each channel on the same instance, events without a channel go round-robin
+    ChannelHash,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -61,10 +223,13 @@ pub enum AcceptError {
 
     #[error("Handshake missing")]
     HandshakeMissing,
+
+    #[error("Plugin '{id}' speaks protocol version {version}, expected {PROTOCOL_VERSION}")]
+    IncompatibleProtocol { id: String, version: u32 },
 }
 
-pub type EventRead<T> = FramedRead<T, Event>;
-pub type EventWrite<T> = FramedWrite<T, Event>;
+pub type EventRead<T> = FramedStream<T, TracedEvent, EventAck>;
+pub type EventWrite<T> = FramedStream<T, EventAck, TracedEvent>;
 
 pub async fn connect<T>(mut transport: T, request: HandshakeRequest) -> io::Result<EventRead<T>>
 where
@@ -84,11 +249,18 @@ where
     let mut handshake_transport: FramedRead<_, _> = frame_transport(&mut transport);
     let timed_request_read = timeout(Duration::from_secs(5), handshake_transport.next());
 
-    let request = timed_request_read
+    let request: HandshakeRequest = timed_request_read
         .await
         .map_err(|_timed_out| AcceptError::HandshakeTimedOut)?
         .ok_or(AcceptError::HandshakeMissing)??;
+    // Older plugins would fail to decode every event they're sent
+    if request.protocol_version != PROTOCOL_VERSION {
+        return Err(AcceptError::IncompatibleProtocol {
+            id: request.id,
+            version: request.protocol_version,
+        });
+    }
 
-    let rpc_transport: FramedWrite<T, Event> = frame_transport(transport);
+    let rpc_transport: EventWrite<T> = frame_transport(transport);
     Ok((request, rpc_transport))
 }
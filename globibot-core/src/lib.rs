@@ -1,7 +1,12 @@
 pub mod events;
 pub mod plugin;
 pub mod rpc;
+pub mod telemetry;
+pub mod trace;
 pub mod transport;
 
+// Bumped whenever the events or RPC wire format changes, version 1 sends events as `TracedEvent`
+pub const PROTOCOL_VERSION: u32 = 1;
+
 pub use serde;
 pub use serenity;
@@ -1,12 +1,13 @@
 use std::{borrow::Borrow, collections::HashSet, convert::Infallible, io, sync::Arc};
 
-use futures::{Future, Stream, StreamExt};
+use futures::{Future, Sink, Stream, StreamExt, channel::mpsc};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::Instrument;
 
 use crate::{
     events,
-    events::{Event, EventRead, EventType},
-    rpc,
+    events::{ConsumerGroup, Event, EventAck, EventRead, EventType, TracedEvent},
+    rpc, trace,
     transport::Protocol,
 };
 
@@ -94,39 +95,83 @@ pub struct ConnectedPlugin<T, Rpc, Events> {
 
 impl<T, Events> ConnectedPlugin<T, <T::RpcPolicy as RpcContext>::Context, Events>
 where
-    Events: Stream<Item = io::Result<events::Event>>,
+    Events: Stream<Item = io::Result<TracedEvent>> + Sink<EventAck, Error = io::Error>,
     T: Plugin + HandleEvents,
     T::Err: std::fmt::Display,
 {
-    pub async fn handle_events(self) -> Result<(), io::Error> {
+    // Resolves with the restart token the bot sent if it shut down, to resume from once reconnected
+    pub async fn handle_events(self) -> Result<Option<String>, io::Error> {
         let Self {
             plugin,
             rpc,
             events,
         } = self;
         let shared_plugin = Arc::new(plugin);
+        let (ack_sink, events) = events.split();
+        let mut restart_token = None;
+        let events = events.inspect(|event_res| {
+            if let Ok(TracedEvent {
+                event: Event::Shutdown {
+                    restart_token: token,
+                },
+                ..
+            }) = event_res
+            {
+                restart_token = Some(token.clone());
+            }
+        });
+        // Events coming from a durable queue are acknowledged once handled successfully
+        let (ack_tx, ack_rx) = mpsc::unbounded();
+
+        let handle_events = events.for_each_concurrent(10, move |event_res| {
+            let rpc = rpc.clone();
+            let plugin = Arc::clone(&shared_plugin);
+            let ack_tx = ack_tx.clone();
+
+            async move {
+                match event_res {
+                    Ok(TracedEvent {
+                        event,
+                        trace,
+                        shard,
+                        ack_id,
+                    }) => {
+                        let span = tracing::info_span!(
+                            "event",
+                            plugin = T::ID,
+                            event_type = ?event.ty(),
+                            shard,
+                        );
+                        if let Some(parent) = &trace {
+                            trace::set_remote_parent(&span, parent);
+                        }
 
-        events
-            .for_each_concurrent(10, move |event_res| {
-                let rpc = rpc.clone();
-                let plugin = Arc::clone(&shared_plugin);
+                        if let Event::Shutdown { .. } = &event {
+                            tracing::info!("The bot is shutting down");
+                        }
 
-                async move {
-                    match event_res {
-                        Ok(event) => {
-                            if let Err(why) = plugin.on_event(rpc, event).await {
-                                tracing::warn!("Failed to handle event: {why}");
+                        let handle_event = plugin.on_event(rpc, event).instrument(span);
+                        match handle_event.await {
+                            Ok(()) => {
+                                if let Some(ack_id) = ack_id {
+                                    ack_tx.unbounded_send(EventAck { ack_id }).ok();
+                                }
                             }
-                        }
-                        Err(why) => {
-                            tracing::error!("Invalid event: {why}");
+                            Err(why) => tracing::warn!("Failed to handle event: {why}"),
                         }
                     }
+                    Err(why) => {
+                        tracing::error!("Invalid event: {why}");
+                    }
                 }
-            })
-            .await;
+            }
+        });
+        let send_acks = ack_rx.map(Ok).forward(ack_sink);
 
-        Ok(())
+        let ((), acks_sent) = futures::join!(handle_events, send_acks);
+        acks_sent?;
+
+        Ok(restart_token)
     }
 }
 
@@ -168,15 +213,50 @@ impl<R> Endpoints<R, UnboundEvents> {
         let events = events.into_iter().map(|e| *e.borrow()).collect();
         Endpoints {
             rpc: self.rpc,
-            events: BoundEvents(protocol, events),
+            events: BoundEvents {
+                addr: protocol,
+                events,
+                topics: HashSet::new(),
+                restart_token: None,
+                group: None,
+            },
         }
     }
 }
 
+impl<R, P> Endpoints<R, BoundEvents<P>> {
+    pub fn topics<T>(mut self, topics: T) -> Self
+    where
+        T: IntoIterator,
+        T::Item: Into<String>,
+    {
+        self.events
+            .topics
+            .extend(topics.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn restart_token(mut self, restart_token: Option<String>) -> Self {
+        self.events.restart_token = restart_token;
+        self
+    }
+
+    pub fn group(mut self, group: Option<ConsumerGroup>) -> Self {
+        self.events.group = group;
+        self
+    }
+}
+
 pub struct UnboundRpc;
 pub struct UnboundEvents;
 pub struct BoundRpc<P>(P);
-pub struct BoundEvents<P>(P, HashSet<EventType>);
+pub struct BoundEvents<P> {
+    addr: P,
+    events: HashSet<EventType>,
+    topics: HashSet<String>,
+    restart_token: Option<String>,
+    group: Option<ConsumerGroup>,
+}
 
 pub trait EndpointPolicy {
     type Policy;
@@ -195,7 +275,7 @@ where
 
     async fn connect(self, plugin_id: String) -> io::Result<Self::Client> {
         let transport = self.0.connect().await?;
-        let handshake_request = rpc::HandshakeRequest { id: plugin_id };
+        let handshake_request = rpc::HandshakeRequest::new(plugin_id);
         let (client, dispatch) =
             rpc::connect(Default::default(), transport, handshake_request).await?;
         tokio::spawn(dispatch);
@@ -212,10 +292,14 @@ where
     type Client = EventRead<P::Client>;
 
     async fn connect(self, plugin_id: String) -> io::Result<Self::Client> {
-        let transport = self.0.connect().await?;
+        let transport = self.addr.connect().await?;
         let handshake_request = events::HandshakeRequest {
             id: plugin_id,
-            events: self.1,
+            events: self.events,
+            topics: self.topics,
+            protocol_version: crate::PROTOCOL_VERSION,
+            restart_token: self.restart_token,
+            group: self.group,
         };
         let events = events::connect(transport, handshake_request).await?;
         Ok(events)
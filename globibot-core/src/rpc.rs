@@ -4,15 +4,20 @@ use futures::{Future, SinkExt, StreamExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serenity::{
-    all::{CommandId, InteractionId, UserId},
+    all::{CommandId, InteractionId, UserId, WebhookId},
     model::{
         application::Command,
-        channel::{Message, ReactionType},
-        id::{ChannelId, GuildId, MessageId},
+        channel::{GuildChannel, Message, ReactionType},
+        guild::ScheduledEvent,
+        id::{ChannelId, ForumTagId, GuildId, MessageId, ScheduledEventId},
         prelude::{Channel, CurrentUser, User},
     },
 };
-use std::{error::Error, io, time::Duration};
+use std::{
+    error::Error,
+    io,
+    time::{Duration, SystemTime},
+};
 use tarpc::{
     ClientMessage, Response, client,
     server::{self, BaseChannel},
@@ -29,6 +34,8 @@ pub trait Protocol {
     async fn current_user() -> CurrentUser;
 
     async fn send_message(chan_id: ChannelId, content: String) -> DiscordApiResult<Message>;
+    // Opens the private channel with the user if there isn't one yet
+    async fn send_dm(user_id: UserId, content: String) -> DiscordApiResult<Message>;
     async fn send_reply(
         chan_id: ChannelId,
         content: String,
@@ -43,7 +50,11 @@ pub trait Protocol {
     ) -> DiscordApiResult<Message>;
     async fn content_safe(content: String, guild_id: Option<GuildId>) -> DiscordApiResult<String>;
 
-    async fn start_typing(chan_id: ChannelId) -> DiscordApiResult<TypingKey>;
+    // The indicator is kept up until `stop_typing`, `max_duration` or the connection dropping
+    async fn start_typing(
+        chan_id: ChannelId,
+        max_duration: Duration,
+    ) -> DiscordApiResult<TypingKey>;
     async fn stop_typing(key: TypingKey) -> DiscordApiResult<()>;
 
     async fn create_global_command(data: Value) -> DiscordApiResult<Command>;
@@ -77,6 +88,80 @@ pub trait Protocol {
 
     async fn get_user(user_id: UserId) -> DiscordApiResult<User>;
     async fn get_channel(channel_id: ChannelId) -> DiscordApiResult<Channel>;
+
+    // Webhooks are created once per channel by the bot and reused, messages sent through them can
+    // take any name and avatar
+    async fn channel_webhook(chan_id: ChannelId) -> DiscordApiResult<WebhookId>;
+    async fn execute_webhook(
+        chan_id: ChannelId,
+        message: WebhookMessage,
+    ) -> DiscordApiResult<Message>;
+
+    async fn create_thread(
+        chan_id: ChannelId,
+        name: String,
+        private: bool,
+    ) -> DiscordApiResult<GuildChannel>;
+    async fn create_thread_from_message(
+        chan_id: ChannelId,
+        message_id: MessageId,
+        name: String,
+    ) -> DiscordApiResult<GuildChannel>;
+    async fn join_thread(thread_id: ChannelId) -> DiscordApiResult<()>;
+    async fn leave_thread(thread_id: ChannelId) -> DiscordApiResult<()>;
+    async fn archive_thread(thread_id: ChannelId, archived: bool)
+    -> DiscordApiResult<GuildChannel>;
+    async fn lock_thread(thread_id: ChannelId, locked: bool) -> DiscordApiResult<GuildChannel>;
+    // Forum posts are threads opened with a first message
+    async fn create_forum_post(
+        forum_id: ChannelId,
+        name: String,
+        content: String,
+        tags: Vec<ForumTagId>,
+    ) -> DiscordApiResult<GuildChannel>;
+
+    async fn create_poll(chan_id: ChannelId, poll: PollMessage) -> DiscordApiResult<Message>;
+    // Closes the poll before its duration is up, results are then final
+    async fn end_poll(chan_id: ChannelId, message_id: MessageId) -> DiscordApiResult<Message>;
+
+    // Discord guild scheduled event objects
+    async fn create_scheduled_event(
+        guild_id: GuildId,
+        data: Value,
+    ) -> DiscordApiResult<ScheduledEvent>;
+    async fn edit_scheduled_event(
+        guild_id: GuildId,
+        event_id: ScheduledEventId,
+        data: Value,
+    ) -> DiscordApiResult<ScheduledEvent>;
+    async fn delete_scheduled_event(
+        guild_id: GuildId,
+        event_id: ScheduledEventId,
+    ) -> DiscordApiResult<()>;
+
+    // Overrides the configured presence on every shard, `None` goes back to it
+    async fn set_presence(presence: Option<Presence>) -> DiscordApiResult<()>;
+
+    async fn kv_get(key: String) -> StorageResult<Option<Value>>;
+    async fn kv_set(key: String, value: Value, ttl: Option<Duration>) -> StorageResult<()>;
+    async fn kv_delete(key: String) -> StorageResult<bool>;
+    async fn kv_list(prefix: String) -> StorageResult<Vec<(String, Value)>>;
+
+    async fn schedule_job(schedule: JobSchedule, payload: Value) -> SchedulerResult<ScheduledJob>;
+    async fn cancel_job(job_id: JobId) -> SchedulerResult<bool>;
+    async fn list_jobs() -> SchedulerResult<Vec<ScheduledJob>>;
+
+    // Number of plugins subscribed to the topic
+    async fn publish(topic: String, payload: Value) -> PluginCallResult<usize>;
+    async fn call_plugin(
+        plugin_id: String,
+        method: String,
+        payload: Value,
+    ) -> PluginCallResult<Value>;
+    async fn respond_to_call(
+        call_id: CallId,
+        result: Result<Value, String>,
+    ) -> PluginCallResult<()>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
@@ -97,14 +182,124 @@ impl From<&'_ str> for DiscordApiError {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+#[error("Storage error: {0}")]
+pub struct StorageError(pub String);
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+#[error("Scheduler error: {0}")]
+pub struct SchedulerError(pub String);
+
+pub type SchedulerResult<T> = Result<T, SchedulerError>;
+
+impl From<StorageError> for SchedulerError {
+    fn from(err: StorageError) -> Self {
+        Self(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JobId(pub u64);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JobSchedule {
+    At(SystemTime),
+    After(Duration),
+    Cron(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: JobId,
+    pub schedule: JobSchedule,
+    pub next_run: SystemTime,
+    pub payload: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebhookMessage {
+    #[serde(default)]
+    pub content: String,
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    // Discord embed objects
+    #[serde(default)]
+    pub embeds: Vec<Value>,
+    #[serde(default)]
+    pub files: Vec<FileAttachment>,
+    // Posts in a thread of the webhook's channel
+    pub thread_id: Option<ChannelId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileAttachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollMessage {
+    pub question: String,
+    pub answers: Vec<String>,
+    // Counted in whole hours by Discord, from 1 hour up to 32 days
+    pub duration: Duration,
+    #[serde(default)]
+    pub allow_multiselect: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    #[serde(default)]
+    pub status: PresenceStatus,
+    pub activity: Option<Activity>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Idle,
+    #[serde(rename = "dnd")]
+    DoNotDisturb,
+    Invisible,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Activity {
+    Playing { name: String },
+    Listening { name: String },
+    Watching { name: String },
+    Competing { name: String },
+    // Free text shown as the bot's custom status
+    Custom { state: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+#[error("Plugin call error: {0}")]
+pub struct PluginCallError(pub String);
+
+pub type PluginCallResult<T> = Result<T, PluginCallError>;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CallId(pub u64);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeRequest {
     pub id: String,
+    #[serde(default)]
+    pub protocol_version: u32,
 }
 
 impl HandshakeRequest {
     pub fn new(id: impl Into<String>) -> Self {
-        Self { id: id.into() }
+        Self {
+            id: id.into(),
+            protocol_version: crate::PROTOCOL_VERSION,
+        }
     }
 }
 
@@ -118,16 +313,40 @@ pub enum AcceptError {
 
     #[error("Handshake missing")]
     HandshakeMissing,
+
+    #[error(
+        "Plugin '{id}' speaks protocol version {version}, expected {}",
+        crate::PROTOCOL_VERSION
+    )]
+    IncompatibleProtocol { id: String, version: u32 },
 }
 
+pub type ProtocolChannel = client::Channel<ProtocolRequest, ProtocolResponse>;
+
 pub async fn connect<T>(
     config: client::Config,
-    mut transport: T,
+    transport: T,
     request: HandshakeRequest,
 ) -> io::Result<(
     ProtocolClient,
     impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>>,
 )>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (channel, dispatch) = connect_channel(config, transport, request).await?;
+    Ok((ProtocolClient::from(channel), dispatch))
+}
+
+// Untyped counterpart of `connect`, for clients forwarding `ProtocolRequest`s as they come
+pub async fn connect_channel<T>(
+    config: client::Config,
+    mut transport: T,
+    request: HandshakeRequest,
+) -> io::Result<(
+    ProtocolChannel,
+    impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>>,
+)>
 where
     T: AsyncRead + AsyncWrite + Unpin,
 {
@@ -135,7 +354,7 @@ where
     handshake_transport.send(request).await?;
 
     let rpc_transport = frame_transport(transport);
-    let client::NewClient { client, dispatch } = ProtocolClient::new(config, rpc_transport);
+    let client::NewClient { client, dispatch } = client::new(config, rpc_transport);
     Ok((client, dispatch.err_into()))
 }
 
@@ -156,12 +375,34 @@ where
         .await
         .map_err(|_timed_out| AcceptError::HandshakeTimedOut)?
         .ok_or(AcceptError::HandshakeMissing)??;
+    if request.protocol_version != crate::PROTOCOL_VERSION {
+        return Err(AcceptError::IncompatibleProtocol {
+            id: request.id,
+            version: request.protocol_version,
+        });
+    }
 
     let rpc_transport = frame_transport(transport);
     let rpc_channel = ServerChannel::new(config, rpc_transport);
     Ok((request, rpc_channel))
 }
 
+// Builds the response to `request` out of the method's output as JSON, for stand-ins answering
+// requests without a real `Protocol` implementation
+pub fn response_for(
+    request: &ProtocolRequest,
+    output: Value,
+) -> serde_json::Result<ProtocolResponse> {
+    let request = serde_json::to_value(request)?;
+    let method = request
+        .as_object()
+        .and_then(|variant| variant.keys().next())
+        .cloned()
+        .unwrap_or_default();
+
+    serde_json::from_value(Value::Object([(method, output)].into_iter().collect()))
+}
+
 slotmap::new_key_type! {
     pub struct TypingKey;
 }
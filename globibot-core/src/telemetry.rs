@@ -0,0 +1,49 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+pub use opentelemetry_otlp::ExporterBuildError;
+
+// Flushes the spans not yet exported when dropped
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+/// Sets up logging and tracing for the bot and its plugins. Spans always get trace IDs so they can
+/// be handed to other processes, they are only exported when an OTLP endpoint is given
+pub fn init(
+    service_name: &str,
+    otlp_endpoint: Option<&str>,
+) -> Result<Telemetry, ExporterBuildError> {
+    let mut provider = SdkTracerProvider::builder().with_resource(
+        Resource::builder()
+            .with_service_name(service_name.to_owned())
+            .build(),
+    );
+    if let Some(endpoint) = otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    let provider = provider.build();
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name.to_owned())))
+        .init();
+
+    Ok(Telemetry { provider })
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(why) = self.provider.shutdown() {
+            tracing::warn!("Failed to flush traces: {why}");
+        }
+    }
+}
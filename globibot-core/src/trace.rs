@@ -0,0 +1,22 @@
+use opentelemetry::trace::{SpanContext, TraceContextExt, TraceState};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use tarpc::trace::Context as TraceContext;
+
+/// Trace context of the current span, only available when an OpenTelemetry layer is installed
+pub fn current() -> Option<TraceContext> {
+    TraceContext::try_from(&Span::current()).ok()
+}
+
+/// Makes `span` a child of a span living in another process
+pub fn set_remote_parent(span: &Span, parent: &TraceContext) {
+    let span_context = SpanContext::new(
+        parent.trace_id.into(),
+        parent.span_id.into(),
+        parent.sampling_decision.into(),
+        true,
+        TraceState::default(),
+    );
+    span.set_parent(opentelemetry::Context::new().with_remote_span_context(span_context));
+}
@@ -1,8 +1,13 @@
-use std::{io, path::Path};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use futures::{
-    Stream, TryFutureExt,
+    Stream, StreamExt, TryFutureExt, TryStreamExt,
     future::{self, Future},
+    stream::BoxStream,
 };
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tokio::{
@@ -11,7 +16,10 @@ use tokio::{
 };
 use tokio_serde::{Framed as SerdeFramed, formats::Json};
 use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::{
+    codec::{Framed, LengthDelimitedCodec},
+    either::Either,
+};
 
 #[derive(Serialize, Deserialize)]
 pub enum NoData {}
@@ -93,3 +101,167 @@ where
         TcpStream::connect(self.addr)
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AddressError {
+    #[error("Unsupported address scheme '{0}', expected 'tcp' or 'unix'")]
+    UnsupportedScheme(String),
+
+    #[error("Address '{0}' is missing a host or path")]
+    Empty(String),
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = match s.split_once("://") {
+            Some(("tcp", addr)) => Address::Tcp(addr.to_owned()),
+            Some(("unix", path)) => Address::Unix(PathBuf::from(path)),
+            Some((scheme, _)) => return Err(AddressError::UnsupportedScheme(scheme.to_owned())),
+            None => Address::Tcp(s.to_owned()),
+        };
+
+        match &address {
+            Address::Tcp(addr) if addr.is_empty() => Err(AddressError::Empty(s.to_owned())),
+            Address::Unix(path) if path.as_os_str().is_empty() => {
+                Err(AddressError::Empty(s.to_owned()))
+            }
+            _ => Ok(address),
+        }
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = AddressError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Address::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+impl Protocol for Address {
+    type Client = Either<TcpStream, UnixStream>;
+    type ClientStream = BoxStream<'static, io::Result<Self::Client>>;
+
+    async fn listen(self) -> io::Result<Self::ClientStream> {
+        Ok(match self {
+            Address::Tcp(addr) => Tcp::new(addr).listen().await?.map_ok(Either::Left).boxed(),
+            Address::Unix(path) => Ipc::new(path).listen().await?.map_ok(Either::Right).boxed(),
+        })
+    }
+
+    async fn connect(self) -> io::Result<Self::Client> {
+        Ok(match self {
+            Address::Tcp(addr) => Either::Left(Tcp::new(addr).connect().await?),
+            Address::Unix(path) => Either::Right(Ipc::new(path).connect().await?),
+        })
+    }
+}
+
+pub trait PeerAddr {
+    fn peer_addr(&self) -> Option<String>;
+}
+
+impl PeerAddr for TcpStream {
+    fn peer_addr(&self) -> Option<String> {
+        TcpStream::peer_addr(self)
+            .ok()
+            .map(|addr| format!("tcp://{addr}"))
+    }
+}
+
+// Unix socket clients are usually unnamed, their process ID is more telling
+impl PeerAddr for UnixStream {
+    fn peer_addr(&self) -> Option<String> {
+        let named = UnixStream::peer_addr(self)
+            .ok()
+            .and_then(|addr| Some(format!("unix://{}", addr.as_pathname()?.display())));
+        named.or_else(|| {
+            let pid = self.peer_cred().ok()?.pid()?;
+            Some(format!("unix (pid {pid})"))
+        })
+    }
+}
+
+impl<L: PeerAddr, R: PeerAddr> PeerAddr for Either<L, R> {
+    fn peer_addr(&self) -> Option<String> {
+        match self {
+            Either::Left(left) => left.peer_addr(),
+            Either::Right(right) => right.peer_addr(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_parsed_by_scheme() {
+        assert_eq!(
+            "tcp://127.0.0.1:4000".parse::<Address>().unwrap(),
+            Address::Tcp("127.0.0.1:4000".to_owned())
+        );
+        assert_eq!(
+            "unix:///run/globibot/rpc.sock".parse::<Address>().unwrap(),
+            Address::Unix(PathBuf::from("/run/globibot/rpc.sock"))
+        );
+    }
+
+    #[test]
+    fn addresses_without_a_scheme_are_tcp() {
+        assert_eq!(
+            "localhost:4000".parse::<Address>().unwrap(),
+            Address::Tcp("localhost:4000".to_owned())
+        );
+    }
+
+    #[test]
+    fn unsupported_schemes_are_rejected() {
+        assert!(matches!(
+            "udp://127.0.0.1:4000".parse::<Address>(),
+            Err(AddressError::UnsupportedScheme(scheme)) if scheme == "udp"
+        ));
+    }
+
+    #[test]
+    fn empty_addresses_are_rejected() {
+        for address in ["", "tcp://", "unix://"] {
+            assert!(
+                matches!(address.parse::<Address>(), Err(AddressError::Empty(_))),
+                "{address:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn addresses_round_trip_through_strings() {
+        for address in ["tcp://0.0.0.0:4000", "unix:///tmp/globibot.sock"] {
+            let parsed = address.parse::<Address>().unwrap();
+            assert_eq!(parsed.to_string(), address);
+        }
+    }
+}
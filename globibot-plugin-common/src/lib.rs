@@ -4,31 +4,221 @@ pub use anyhow;
 pub use gif;
 pub use image;
 
-pub fn load_env(key: &str) -> String {
-    std::env::var(key)
-        .unwrap_or_else(|why| panic!("Failed to load environment variable '{key}': {why}"))
+pub mod config {
+    use std::{
+        collections::{HashMap, HashSet},
+        env, fs,
+        path::PathBuf,
+    };
+
+    use anyhow::Context;
+    use serde::{Deserialize, de::DeserializeOwned};
+    use serde_path_to_error::Segment;
+    use toml::{Table, Value};
+
+    const DEFAULT_PATH: &str = "globibot.toml";
+
+    /// Loads the `[plugins.<plugin_id>]` section of the shared configuration file, overridden by
+    /// `<PLUGIN_ID>_<KEY>` environment variables
+    pub fn load<T: DeserializeOwned>(plugin_id: &str) -> anyhow::Result<T> {
+        load_with_fallbacks(plugin_id, &[])
+    }
+
+    /// Same as [`load`], with `(key, variable)` fallbacks read from the environment for keys that
+    /// are set nowhere else
+    pub fn load_with_fallbacks<T: DeserializeOwned>(
+        plugin_id: &str,
+        fallbacks: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        let section = read_file()?
+            .remove("plugins")
+            .and_then(|plugins| plugins.as_table()?.get(plugin_id).cloned())
+            .and_then(|section| section.as_table().cloned())
+            .unwrap_or_default();
+
+        let prefix = format!("{}_", plugin_id.to_uppercase().replace('-', "_"));
+        let mut overrides = env::vars()
+            .filter_map(|(var, raw)| Some((var.strip_prefix(&prefix)?.to_lowercase(), raw)))
+            .collect::<HashMap<_, _>>();
+        for (key, var) in fallbacks {
+            if !section.contains_key(*key)
+                && !overrides.contains_key(*key)
+                && let Ok(raw) = env::var(var)
+            {
+                overrides.insert(key.to_string(), raw);
+            }
+        }
+
+        // Overrides are kept as strings unless the field they set expects something else
+        let mut typed = HashSet::new();
+        loop {
+            let mut table = section.clone();
+            for (key, raw) in &overrides {
+                let value = match typed.contains(key) {
+                    true => env_value(raw),
+                    false => Value::String(raw.clone()),
+                };
+                table.insert(key.clone(), value);
+            }
+
+            let err = match serde_path_to_error::deserialize(Value::Table(table)) {
+                Ok(config) => return Ok(config),
+                Err(err) => err,
+            };
+            if let Some(Segment::Map { key }) = err.path().iter().next()
+                && overrides.contains_key(key)
+                && typed.insert(key.clone())
+            {
+                continue;
+            }
+
+            return Err(err)
+                .with_context(|| format!("Invalid configuration for plugin '{plugin_id}'"));
+        }
+    }
+
+    /// Loads the `[telemetry]` section shared with the bot, overridden by `OTLP_ENDPOINT`
+    pub fn telemetry() -> anyhow::Result<Telemetry> {
+        let mut section = read_file()?
+            .remove("telemetry")
+            .and_then(|section| section.as_table().cloned())
+            .unwrap_or_default();
+
+        if let Ok(endpoint) = env::var("OTLP_ENDPOINT") {
+            section.insert("otlp_endpoint".to_owned(), Value::String(endpoint));
+        }
+
+        Value::Table(section)
+            .try_into()
+            .context("Invalid telemetry configuration")
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    pub struct Telemetry {
+        pub otlp_endpoint: Option<String>,
+    }
+
+    fn read_file() -> anyhow::Result<Table> {
+        let Some(path) = config_path() else {
+            return Ok(Table::new());
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid config file '{}'", path.display()))
+    }
+
+    fn config_path() -> Option<PathBuf> {
+        match env::var_os("GLOBIBOT_CONFIG") {
+            Some(path) => Some(path.into()),
+            None => Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists()),
+        }
+    }
+
+    fn env_value(raw: &str) -> Value {
+        if let Ok(integer) = raw.parse() {
+            Value::Integer(integer)
+        } else if let Ok(float) = raw.parse() {
+            Value::Float(float)
+        } else if let Ok(boolean) = raw.parse() {
+            Value::Boolean(boolean)
+        } else {
+            Value::String(raw.to_owned())
+        }
+    }
+}
+
+pub mod telemetry {
+    pub use globibot_core::telemetry::Telemetry;
+
+    /// Sets up logging and tracing, spans handling an event become children of the bot's spans
+    /// and are exported when an OTLP endpoint is configured
+    pub fn init(plugin_id: &str) -> anyhow::Result<Telemetry> {
+        let config = crate::config::telemetry()?;
+        let service_name = format!("globibot-plugin-{plugin_id}");
+
+        Ok(globibot_core::telemetry::init(
+            &service_name,
+            config.otlp_endpoint.as_deref(),
+        )?)
+    }
 }
 
 pub mod endpoints {
+    use std::{env, fs, io};
+
     use anyhow::Context;
     use globibot_core::{
-        events::EventType,
+        events::{Balance, ConsumerGroup, EventType},
         plugin::{BoundEvents, BoundRpc, Endpoints},
-        transport::Tcp,
+        transport::Address,
     };
 
-    type TcpEndpoints = Endpoints<BoundRpc<Tcp<String>>, BoundEvents<Tcp<String>>>;
+    type AddressEndpoints = Endpoints<BoundRpc<Address>, BoundEvents<Address>>;
 
-    pub fn tcp_from_env(
+    pub fn from_env(
         events: impl IntoIterator<Item = EventType>,
-    ) -> anyhow::Result<TcpEndpoints> {
-        let subscriber_addr = std::env::var("SUBSCRIBER_ADDR")
-            .context("Missing 'SUBSCRIBER_ADDR' environment variable")?;
-        let rpc_addr =
-            std::env::var("RPC_ADDR").context("Missing 'RPC_ADDR' environment variable")?;
+    ) -> anyhow::Result<AddressEndpoints> {
+        let subscriber_addr = address_from_env("SUBSCRIBER_ADDR")?;
+        let rpc_addr = address_from_env("RPC_ADDR")?;
+        let restart_token = restart_token_from_env()?;
+        let group = group_from_env()?;
 
         Ok(Endpoints::new()
-            .rpc(Tcp::new(rpc_addr))
-            .events(Tcp::new(subscriber_addr), events))
+            .rpc(rpc_addr)
+            .events(subscriber_addr, events)
+            .restart_token(restart_token)
+            .group(group))
+    }
+
+    /// Keeps the token the bot sent when shutting down in `RESTART_TOKEN_FILE`, if set, so that
+    /// the plugin gets the events it missed once started again
+    pub fn save_restart_token(restart_token: Option<String>) -> anyhow::Result<()> {
+        let (Some(path), Some(restart_token)) = (env::var_os("RESTART_TOKEN_FILE"), restart_token)
+        else {
+            return Ok(());
+        };
+
+        fs::write(&path, restart_token)
+            .with_context(|| format!("Failed to save restart token to '{}'", path.display()))
+    }
+
+    fn restart_token_from_env() -> anyhow::Result<Option<String>> {
+        // Set by the bot's supervisor when it restarts plugins after a restart of its own
+        if let Ok(restart_token) = env::var("RESTART_TOKEN") {
+            return Ok(Some(restart_token));
+        }
+        let Some(path) = env::var_os("RESTART_TOKEN_FILE") else {
+            return Ok(None);
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(restart_token) => Ok(Some(restart_token.trim().to_owned())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)
+                .with_context(|| format!("Failed to read restart token from '{}'", path.display())),
+        }
+    }
+
+    // Replicas of a plugin set the same `PLUGIN_GROUP` to split its events between them
+    fn group_from_env() -> anyhow::Result<Option<ConsumerGroup>> {
+        let Ok(id) = std::env::var("PLUGIN_GROUP") else {
+            return Ok(None);
+        };
+        let balance = match std::env::var("PLUGIN_GROUP_BALANCE").as_deref() {
+            Err(_) | Ok("round_robin") => Balance::RoundRobin,
+            Ok("channel_hash") => Balance::ChannelHash,
+            Ok(other) => anyhow::bail!("Unknown 'PLUGIN_GROUP_BALANCE': {other}"),
+        };
+
+        Ok(Some(ConsumerGroup { id, balance }))
+    }
+
+    fn address_from_env(key: &str) -> anyhow::Result<Address> {
+        let addr =
+            std::env::var(key).with_context(|| format!("Missing '{key}' environment variable"))?;
+        addr.parse()
+            .with_context(|| format!("Malformed '{key}' environment variable"))
     }
 }
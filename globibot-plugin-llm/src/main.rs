@@ -4,45 +4,70 @@ mod personality;
 use openrouter::{ContentPart, ImageContentPart, Message as LlmMessage, Role, TextContentPart};
 use parking_lot::Mutex;
 
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use globibot_core::{
     events::{Event, EventType},
     plugin::{HandleEvents, HasEvents, HasRpc, Plugin},
     rpc,
     serenity::all::{
-        ChannelId, CommandDataOptionValue, CommandId, CommandInteraction, Message, UserId,
+        ChannelId, CommandDataOptionValue, CommandId, CommandInteraction, GuildId, Message, UserId,
     },
 };
 use itertools::Itertools;
+use serde::Deserialize;
 
 use crate::personality::Personality;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let _telemetry = common::telemetry::init(LlmPlugin::ID)?;
 
-    let guild_id = std::env::var("LLM_INSTALL_COMMAND_GUILD_ID")?.parse()?;
+    // Deployments predating the shared config file set the bot's ID under its old name
+    let config: Config =
+        common::config::load_with_fallbacks(LlmPlugin::ID, &[("bot_id", "DISCORD_BOT_ID")])?;
     let desired_command: serde_json::Value =
         serde_json::from_str(include_str!("../llm-slash-command.json"))?;
 
-    let endpoints =
-        common::endpoints::tcp_from_env([EventType::MessageCreate, EventType::InteractionCreate])?;
+    let endpoints = common::endpoints::from_env([
+        EventType::MessageCreate,
+        EventType::DirectMessageCreate,
+        EventType::InteractionCreate,
+    ])?;
 
     let plugin = LlmPlugin::connect_init(endpoints, async |rpc| {
         let command = rpc
-            .upsert_guild_command(rpc::context::current(), guild_id, desired_command)
+            .upsert_guild_command(
+                rpc::context::current(),
+                config.install_command_guild_id,
+                desired_command,
+            )
             .await??;
 
-        LlmPlugin::from_env(command.id)
+        let plugin = LlmPlugin::new(config, command.id);
+        plugin.restore_settings(rpc).await?;
+        anyhow::Ok(plugin)
     })
     .await?;
 
-    plugin.handle_events().await?;
+    let restart_token = plugin.handle_events().await?;
+    common::endpoints::save_restart_token(restart_token)?;
 
     Ok(())
 }
 
+#[derive(Debug, Deserialize)]
+struct Config {
+    install_command_guild_id: GuildId,
+    bot_id: UserId,
+    admin_user_id: UserId,
+    openrouter_api_key: String,
+    default_model_id: String,
+}
+
 struct LlmPlugin {
     bot_id: UserId,
     admin_id: UserId,
@@ -53,18 +78,37 @@ struct LlmPlugin {
 }
 
 impl LlmPlugin {
-    fn from_env(command_id: CommandId) -> anyhow::Result<Self> {
-        let bot_id = std::env::var("DISCORD_BOT_ID")?.parse()?;
-        let admin_id = std::env::var("LLM_ADMIN_USER_ID")?.parse()?;
-        let llm_client = Mutex::new(openrouter::Client::from_env()?);
-
-        Ok(LlmPlugin {
-            bot_id,
-            admin_id,
-            llm_client,
+    fn new(config: Config, command_id: CommandId) -> Self {
+        let llm_client =
+            openrouter::Client::new(config.openrouter_api_key, config.default_model_id);
+
+        LlmPlugin {
+            bot_id: config.bot_id,
+            admin_id: config.admin_user_id,
+            llm_client: Mutex::new(llm_client),
             contexts_by_channel: <_>::default(),
             command_id,
-        })
+        }
+    }
+
+    async fn restore_settings(&self, rpc: &rpc::ProtocolClient) -> anyhow::Result<()> {
+        let ctx = rpc::context::current();
+
+        let model = rpc.kv_get(ctx, MODEL_KEY.to_owned()).await??;
+        if let Some(model) = model.as_ref().and_then(|v| v.as_str()) {
+            self.llm_client.lock().model = model.to_owned();
+        }
+
+        let personality = rpc.kv_get(ctx, PERSONALITY_KEY.to_owned()).await??;
+        if let Some(personality) = personality
+            .as_ref()
+            .and_then(|v| v.as_str())
+            .and_then(|p| Personality::try_from(p).ok())
+        {
+            self.llm_client.lock().personality = personality;
+        }
+
+        Ok(())
     }
 
     async fn answer_message(
@@ -78,7 +122,9 @@ impl LlmPlugin {
         let mut parts = self.context_for_channel(message.channel_id);
         parts.push(user_llm_message.clone());
 
-        let typing = rpc.start_typing(ctx, message.channel_id).await??;
+        let typing = rpc
+            .start_typing(ctx, message.channel_id, TYPING_LEASE)
+            .await??;
         let completion = self.llm_client.lock().complete(parts);
         let completion_res = completion.await;
         rpc.stop_typing(ctx, typing).await??;
@@ -180,7 +226,16 @@ impl LlmPlugin {
             && opt.name == "model"
             && let Some(new_model) = opt.value.as_str()
         {
-            self.llm_client.lock().model = new_model.trim().to_string();
+            let new_model = new_model.trim();
+            self.llm_client.lock().model = new_model.to_string();
+            rpc.kv_set(
+                rpc::context::current(),
+                MODEL_KEY.to_owned(),
+                serde_json::json!(new_model),
+                None,
+            )
+            .await??;
+
             rpc.create_interaction_response(
                 rpc::context::current(),
                 interaction.id,
@@ -249,6 +304,13 @@ impl LlmPlugin {
             };
 
             self.llm_client.lock().personality = new_personality;
+            rpc.kv_set(
+                rpc::context::current(),
+                PERSONALITY_KEY.to_owned(),
+                serde_json::json!(new_personality.to_string()),
+                None,
+            )
+            .await??;
             self.contexts_by_channel
                 .lock()
                 .remove(&interaction.channel_id);
@@ -272,6 +334,11 @@ impl LlmPlugin {
 }
 
 const CONTEXT_WINDOW_SIZE: usize = 200;
+// Upper bound on a completion, the indicator stops earlier once the answer is in
+const TYPING_LEASE: Duration = Duration::from_secs(2 * 60);
+
+const MODEL_KEY: &str = "model";
+const PERSONALITY_KEY: &str = "personality";
 
 impl Plugin for LlmPlugin {
     const ID: &'static str = "llm";
@@ -315,7 +382,9 @@ impl HandleEvents for LlmPlugin {
                 }
             }
 
-            Event::MessageCreate { message } if !message.author.bot => {
+            Event::MessageCreate { message } | Event::DirectMessageCreate { message }
+                if !message.author.bot =>
+            {
                 let user_name = &message.author.name;
                 let user_id = message.author.id.get();
 
@@ -352,7 +421,8 @@ impl HandleEvents for LlmPlugin {
                     }
                 };
 
-                if message.mentions_user_id(self.bot_id) {
+                // Private conversations don't need the bot to be mentioned
+                if message.guild_id.is_none() || message.mentions_user_id(self.bot_id) {
                     self.answer_message(rpc, &message, user_llm_message).await?;
                 } else {
                     self.register_message(&message, user_llm_message);
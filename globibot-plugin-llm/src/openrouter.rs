@@ -11,16 +11,13 @@ pub struct Client {
 }
 
 impl Client {
-    pub fn from_env() -> anyhow::Result<Self> {
-        let api_key = std::env::var("LLM_OPENROUTER_API_KEY")?;
-        let model = std::env::var("LLM_DEFAULT_MODEL_ID")?;
-
-        Ok(Self {
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
             api_key,
             model,
             personality: <_>::default(),
             http_client: reqwest::Client::new(),
-        })
+        }
     }
 
     pub fn complete<Parts: IntoIterator<Item = Message> + Send>(
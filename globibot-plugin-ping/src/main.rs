@@ -9,12 +9,18 @@ use serenity::model::{channel::Message, id::MessageId};
 
 #[tokio::main]
 async fn main() -> common::anyhow::Result<()> {
+    let _telemetry = common::telemetry::init(PingPlugin::ID)?;
+
     let plugin = PingPlugin::default();
 
-    let endpoints =
-        common::endpoints::tcp_from_env([EventType::MessageCreate, EventType::MessageDelete])?;
+    let endpoints = common::endpoints::from_env([
+        EventType::MessageCreate,
+        EventType::DirectMessageCreate,
+        EventType::MessageDelete,
+    ])?;
 
-    plugin.connect(endpoints).await?.handle_events().await?;
+    let restart_token = plugin.connect(endpoints).await?.handle_events().await?;
+    common::endpoints::save_restart_token(restart_token)?;
 
     Ok(())
 }
@@ -36,7 +42,9 @@ impl HandleEvents for PingPlugin {
 
     async fn on_event(&self, rpc: rpc::ProtocolClient, event: Event) -> Result<(), Self::Err> {
         match event {
-            Event::MessageCreate { message } if message.content.starts_with("!ping") => {
+            Event::MessageCreate { message } | Event::DirectMessageCreate { message }
+                if message.content.starts_with("!ping") =>
+            {
                 let orig_message_id = message.id;
                 let message = rpc
                     .send_message(rpc::context::current(), message.channel_id, "pong!".into())
@@ -1,16 +1,20 @@
 use std::{
     error::Error,
+    path::PathBuf,
     time::{Duration, Instant},
 };
 
+use serde::{Deserialize, Serialize};
+
 use globibot_core::{
     events::{Event, EventType},
     plugin::{HandleEvents, HasEvents, HasRpc, Plugin},
-    rpc::{self, context::current as rpc_context},
+    rpc::{self, JobSchedule, context::current as rpc_context},
     serenity::{
         all::CommandId,
         model::{
             application::{CommandDataOptionValue, CommandInteraction},
+            channel::Message,
             id::UserId,
             mention::Mentionable,
         },
@@ -26,14 +30,19 @@ type PluginError = Box<dyn Error + Send + Sync>;
 
 #[tokio::main]
 async fn main() -> common::anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let _telemetry = common::telemetry::init(RatemePlugin::<rand::rngs::StdRng>::ID)?;
 
-    let img_path = load_env("RATEME_IMG_PATH");
-    let rating_images_small = load_rating_images(&img_path, (25, 25))?;
-    let rating_images_medium = load_rating_images(&img_path, (50, 50))?;
+    let config: Config = common::config::load(RatemePlugin::<rand::rngs::StdRng>::ID)?;
+    let rating_images_small = load_rating_images(&config.img_path, (25, 25))?;
+    let rating_images_medium = load_rating_images(&config.img_path, (50, 50))?;
 
-    let endpoints =
-        common::endpoints::tcp_from_env([EventType::MessageCreate, EventType::InteractionCreate])?;
+    let endpoints = common::endpoints::from_env([
+        EventType::MessageCreate,
+        EventType::DirectMessageCreate,
+        EventType::InteractionCreate,
+        EventType::ScheduledJob,
+        EventType::PluginCall,
+    ])?;
 
     let desired_command: serde_json::Value =
         serde_json::from_str(include_str!("../rateme-slash-command.json"))?;
@@ -52,14 +61,15 @@ async fn main() -> common::anyhow::Result<()> {
     })
     .await?;
 
-    plugin.handle_events().await?;
+    let restart_token = plugin.handle_events().await?;
+    common::endpoints::save_restart_token(restart_token)?;
 
     Ok(())
 }
 
-fn load_env(key: &str) -> String {
-    std::env::var(key)
-        .unwrap_or_else(|why| panic!("Failed to load environment variable '{}': {}", key, why))
+#[derive(Debug, Deserialize)]
+struct Config {
+    img_path: PathBuf,
 }
 
 struct RatemePlugin<R: Rng> {
@@ -106,7 +116,7 @@ impl<R: Rng + Send + 'static> HandleEvents for RatemePlugin<R> {
 
     async fn on_event(&self, rpc: rpc::ProtocolClient, event: Event) -> Result<(), Self::Err> {
         match event {
-            Event::MessageCreate { message: _ } => {}
+            Event::MessageCreate { message: _ } | Event::DirectMessageCreate { message: _ } => {}
             Event::InteractionCreate { interaction } if interaction.data.id == self.command_id => {
                 let CommandInteraction {
                     id,
@@ -182,7 +192,6 @@ impl<R: Rng + Send + 'static> HandleEvents for RatemePlugin<R> {
                     .send_file(rpc_context(), channel_id, gif, "rate.gif".to_owned())
                     .await??;
 
-                tokio::time::sleep(Duration::from_secs(19)).await;
                 let p2content = format!(
                     "Looks like {} an __HB{}__ {}",
                     match target {
@@ -192,7 +201,32 @@ impl<R: Rng + Send + 'static> HandleEvents for RatemePlugin<R> {
                     rate as u8,
                     rate.emote()
                 );
-                rpc.edit_message(rpc_context(), p2message, p2content)
+                let reveal = Reveal {
+                    message: p2message,
+                    content: p2content,
+                };
+                rpc.schedule_job(
+                    rpc_context(),
+                    JobSchedule::After(Duration::from_secs(19)),
+                    serde_json::to_value(reveal)?,
+                )
+                .await??;
+            }
+            Event::ScheduledJob { job } => {
+                let Reveal { message, content } = serde_json::from_value(job.payload)?;
+                rpc.edit_message(rpc_context(), message, content).await??;
+            }
+            Event::PluginCall {
+                call_id, method, ..
+            } => {
+                let result = match method.as_str() {
+                    "rate" => {
+                        let rate = self.rng.lock().random::<Rate>();
+                        Ok(serde_json::json!({ "rate": rate as u8, "emote": rate.emote() }))
+                    }
+                    _ => Err(format!("Unknown method: {method}")),
+                };
+                rpc.respond_to_call(rpc_context(), call_id, result)
                     .await??;
             }
             _ => {}
@@ -201,6 +235,12 @@ impl<R: Rng + Send + 'static> HandleEvents for RatemePlugin<R> {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct Reveal {
+    message: Message,
+    content: String,
+}
+
 #[derive(Debug, Clone, Copy)]
 enum RateTarget {
     User(UserId),
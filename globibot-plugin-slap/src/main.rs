@@ -7,26 +7,19 @@ use globibot_core::{
     plugin::{HandleEvents, HasEvents, HasRpc, Plugin},
     rpc::{self, context::current as rpc_context},
     serenity::{
-        all::CommandId,
+        all::{CommandId, GuildId},
         model::application::{CommandDataOptionValue, CommandInteraction},
         prelude::Mentionable,
     },
 };
 use rand::Rng;
-use std::time::Instant;
+use serde::Deserialize;
+use std::{path::PathBuf, time::Instant};
 
 pub mod scenario {
     pub mod animated_slap;
     pub mod static_slap;
 
-    use std::path::PathBuf;
-
-    fn img_path(file_name: &str) -> PathBuf {
-        let mut path = PathBuf::from(common::load_env("SLAP_IMG_PATH"));
-        path.push(file_name);
-        path
-    }
-
     #[derive(Debug, Clone)]
     pub struct SlapScenario {
         pub dim: (u16, u16),
@@ -39,25 +32,38 @@ pub mod scenario {
 
 use scenario::SlapScenario;
 
+#[derive(Debug, Deserialize)]
+struct Config {
+    img_path: PathBuf,
+    install_command_guild_id: GuildId,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let _telemetry = common::telemetry::init(SlapPlugin::ID)?;
 
-    let guild_id = std::env::var("SLAP_INSTALL_COMMAND_GUILD_ID")?.parse()?;
+    let config: Config = common::config::load(SlapPlugin::ID)?;
     let desired_command: serde_json::Value =
         serde_json::from_str(include_str!("../slap-slash-command.json"))?;
 
-    let endpoints =
-        common::endpoints::tcp_from_env([EventType::MessageCreate, EventType::InteractionCreate])?;
+    let endpoints = common::endpoints::from_env([
+        EventType::MessageCreate,
+        EventType::DirectMessageCreate,
+        EventType::InteractionCreate,
+    ])?;
 
     let slap_scenarios = vec![
-        scenario::static_slap::load_scenario()?,
-        scenario::animated_slap::load_scenario()?,
+        scenario::static_slap::load_scenario(&config.img_path)?,
+        scenario::animated_slap::load_scenario(&config.img_path)?,
     ];
 
     let plugin = SlapPlugin::connect_init(endpoints, async |rpc| {
         let command = rpc
-            .upsert_guild_command(rpc_context(), guild_id, desired_command)
+            .upsert_guild_command(
+                rpc_context(),
+                config.install_command_guild_id,
+                desired_command,
+            )
             .await??;
 
         anyhow::Ok(SlapPlugin {
@@ -67,7 +73,8 @@ async fn main() -> anyhow::Result<()> {
     })
     .await?;
 
-    plugin.handle_events().await?;
+    let restart_token = plugin.handle_events().await?;
+    common::endpoints::save_restart_token(restart_token)?;
 
     Ok(())
 }
@@ -128,7 +135,7 @@ impl HandleEvents for SlapPlugin {
 
     async fn on_event(&self, rpc: rpc::ProtocolClient, event: Event) -> Result<(), Self::Err> {
         match event {
-            Event::MessageCreate { message: _ } => {}
+            Event::MessageCreate { message: _ } | Event::DirectMessageCreate { message: _ } => {}
             Event::InteractionCreate { interaction } if interaction.data.id == self.command_id => {
                 let CommandInteraction {
                     id,
@@ -1,9 +1,11 @@
 use super::SlapScenario;
 
+use std::path::Path;
+
 use common::{anyhow, imageops};
 
-pub fn load_scenario() -> anyhow::Result<SlapScenario> {
-    let path = super::img_path("slap-animated.gif");
+pub fn load_scenario(img_path: &Path) -> anyhow::Result<SlapScenario> {
+    let path = img_path.join("slap-animated.gif");
     let frames = imageops::load_gif(path, DIMENSIONS)?;
 
     let scenario = SlapScenario {
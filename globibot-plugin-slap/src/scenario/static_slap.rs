@@ -1,9 +1,11 @@
 use super::SlapScenario;
 
+use std::path::Path;
+
 use common::anyhow;
 
-pub fn load_scenario() -> anyhow::Result<SlapScenario> {
-    let path = super::img_path("slap-hd.png");
+pub fn load_scenario(img_path: &Path) -> anyhow::Result<SlapScenario> {
+    let path = img_path.join("slap-hd.png");
     let image = common::image::open(path)?.into_rgba8();
 
     let scenario = SlapScenario {
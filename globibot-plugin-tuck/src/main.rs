@@ -15,9 +15,15 @@ use globibot_plugin_tuck::{
     AvatarPositions, Dimension, PasteAvatarPositions, load_gif, paste_avatar,
 };
 use rand::Rng;
+use serde::Deserialize;
 
 type PluginError = Box<dyn Error + Send + Sync>;
 
+#[derive(Debug, Deserialize)]
+struct Config {
+    img_path: PathBuf,
+}
+
 #[derive(Debug, Clone)]
 struct TuckGifDescriptor {
     file_name: &'static str,
@@ -88,10 +94,12 @@ const TUCK_GIF_DESCRIPTORS: [TuckGifDescriptor; 4] = [
 
 #[tokio::main]
 async fn main() -> common::anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let _telemetry = common::telemetry::init(TuckPlugin::<{ TUCK_GIF_DESCRIPTORS.len() }>::ID)?;
+
+    let config: Config = common::config::load(TuckPlugin::<{ TUCK_GIF_DESCRIPTORS.len() }>::ID)?;
 
     let tuck_gifs = TUCK_GIF_DESCRIPTORS.map(|d| {
-        let mut img_path = PathBuf::from(common::load_env("TUCK_IMG_PATH"));
+        let mut img_path = config.img_path.clone();
         img_path.push(d.file_name);
         let mut gif = load_gif(img_path, d.dimension).expect("Failed to load gif");
         if let Some(range) = &d.frame_range {
@@ -100,8 +108,11 @@ async fn main() -> common::anyhow::Result<()> {
         (d, gif)
     });
 
-    let endpoints =
-        common::endpoints::tcp_from_env([EventType::MessageCreate, EventType::InteractionCreate])?;
+    let endpoints = common::endpoints::from_env([
+        EventType::MessageCreate,
+        EventType::DirectMessageCreate,
+        EventType::InteractionCreate,
+    ])?;
 
     let desired_command: serde_json::Value =
         serde_json::from_str(include_str!("../tuck-slash-command.json"))?;
@@ -118,7 +129,8 @@ async fn main() -> common::anyhow::Result<()> {
     })
     .await?;
 
-    plugin.handle_events().await?;
+    let restart_token = plugin.handle_events().await?;
+    common::endpoints::save_restart_token(restart_token)?;
 
     Ok(())
 }
@@ -170,7 +182,7 @@ impl<const GIF_COUNT: usize> HandleEvents for TuckPlugin<GIF_COUNT> {
 
     async fn on_event(&self, rpc: rpc::ProtocolClient, event: Event) -> Result<(), Self::Err> {
         match event {
-            Event::MessageCreate { message: _ } => {}
+            Event::MessageCreate { message: _ } | Event::DirectMessageCreate { message: _ } => {}
             Event::InteractionCreate { interaction } if interaction.data.id == self.command_id => {
                 let CommandInteraction {
                     id,
@@ -0,0 +1,162 @@
+mod stub;
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use globibot_core::{
+    events::{self, EventWrite, HandshakeRequest, RecordedEvent},
+    rpc,
+    transport::{Address, Protocol},
+};
+use tarpc::server::Channel;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{info, warn};
+
+/// Replays a recorded event stream to plugins
+#[derive(Debug, Parser)]
+struct Args {
+    /// JSON-lines recording written by the bot through `EVENTS_RECORD_PATH`
+    recording: PathBuf,
+
+    /// Address to accept event subscribers on, `tcp://host:port` or `unix:///path`
+    #[arg(long, env = "SUBSCRIBER_ADDR")]
+    subscriber_addr: Address,
+
+    /// Address to serve a stub RPC server on, answering every Discord call with an error
+    #[arg(long, env = "RPC_ADDR")]
+    rpc_addr: Option<Address>,
+
+    /// Replay speed multiplier applied to the delays between recorded events
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+    anyhow::ensure!(args.speed > 0.0, "Replay speed must be positive");
+
+    let recording = Arc::new(load_recording(&args.recording).await?);
+    info!("Loaded {} recorded events", recording.len());
+
+    futures::try_join!(
+        replay_events(args.subscriber_addr, recording, args.speed),
+        serve_stub_rpc(args.rpc_addr),
+    )?;
+
+    Ok(())
+}
+
+async fn load_recording(path: &PathBuf) -> anyhow::Result<Vec<RecordedEvent>> {
+    let data = tokio::fs::read_to_string(path).await?;
+
+    let recording = data
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(idx, line)| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(why) => {
+                warn!("Skipping invalid record on line {}: {why}", idx + 1);
+                None
+            }
+        })
+        .collect();
+
+    Ok(recording)
+}
+
+async fn replay_events<P>(
+    protocol: P,
+    recording: Arc<Vec<RecordedEvent>>,
+    speed: f64,
+) -> anyhow::Result<()>
+where
+    P: Protocol,
+    P::Client: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut subscribers = std::pin::pin!(protocol.listen().await?);
+
+    while let Some(transport) = subscribers.next().await.transpose()? {
+        match events::accept(transport).await {
+            Ok((request, subscriber)) => {
+                info!("Replaying to subscriber '{id}'", id = request.id);
+                tokio::spawn(replay_to(
+                    request,
+                    subscriber,
+                    Arc::clone(&recording),
+                    speed,
+                ));
+            }
+            Err(why) => warn!("Failed to accept subscriber: {why}"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn replay_to<T>(
+    request: HandshakeRequest,
+    mut subscriber: EventWrite<T>,
+    recording: Arc<Vec<RecordedEvent>>,
+    speed: f64,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut previous_at = None;
+    let mut sent = 0;
+
+    for RecordedEvent { at, event } in recording.iter() {
+        if let Some(previous_at) = previous_at {
+            let gap = at.duration_since(previous_at).unwrap_or(Duration::ZERO);
+            tokio::time::sleep(gap.div_f64(speed)).await;
+        }
+        previous_at = Some(*at);
+
+        if !request.events.contains(&event.ty()) {
+            continue;
+        }
+
+        if let Err(why) = subscriber.send(event.clone().into()).await {
+            warn!("Failed to send event to '{id}': {why}", id = request.id);
+            return;
+        }
+        sent += 1;
+    }
+
+    info!(
+        "Finished replaying {sent} events to '{id}'",
+        id = request.id
+    );
+}
+
+async fn serve_stub_rpc<P>(protocol: Option<P>) -> anyhow::Result<()>
+where
+    P: Protocol,
+    P::Client: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let Some(protocol) = protocol else {
+        return Ok(());
+    };
+
+    let mut clients = std::pin::pin!(protocol.listen().await?);
+
+    while let Some(transport) = clients.next().await.transpose()? {
+        match rpc::accept(Default::default(), transport).await {
+            Ok((request, client)) => {
+                info!("Serving stub RPC to '{id}'", id = request.id);
+                tokio::spawn(client.execute(tarpc::server::serve(stub::serve)).for_each(
+                    |response| async {
+                        tokio::spawn(response);
+                    },
+                ));
+            }
+            Err(why) => warn!("Failed to accept RPC client: {why}"),
+        }
+    }
+
+    Ok(())
+}
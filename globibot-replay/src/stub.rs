@@ -0,0 +1,30 @@
+use globibot_core::{
+    rpc::{self, ProtocolRequest, ProtocolResponse},
+    serenity::model::user::CurrentUser,
+};
+use serde_json::json;
+use tarpc::{ServerError, context::Context};
+use tracing::info;
+
+pub async fn serve(
+    _ctx: Context,
+    request: ProtocolRequest,
+) -> Result<ProtocolResponse, ServerError> {
+    info!("RPC call: {request:?}");
+    stub_response(&request)
+        .map_err(|err| ServerError::new(std::io::ErrorKind::InvalidData, err.to_string()))
+}
+
+// Methods returning a `Result` fail with a plain string error, unless they can't do any harm
+fn stub_response(request: &ProtocolRequest) -> serde_json::Result<ProtocolResponse> {
+    match request {
+        ProtocolRequest::CurrentUser {} => {
+            Ok(ProtocolResponse::CurrentUser(CurrentUser::default()))
+        }
+        ProtocolRequest::SetPresence { .. } => Ok(ProtocolResponse::SetPresence(Ok(()))),
+        request => rpc::response_for(
+            request,
+            json!({ "Err": "No Discord API available while replaying" }),
+        ),
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashSet, process::Stdio};
+
+use clap::Parser;
+use futures::{StreamExt, channel::mpsc::UnboundedSender};
+use globibot_core::{
+    events::{self, Balance, ConsumerGroup, Event, EventAck, EventType, TracedEvent},
+    rpc::{self, ProtocolChannel, ProtocolRequest, ProtocolResponse},
+    trace::TraceContext,
+    transport::{Address, Protocol},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{ChildStdin, ChildStdout, Command},
+    sync::mpsc,
+};
+use tracing::{debug, warn};
+
+/// Runs a command as a plugin, exchanging newline-delimited JSON with it: events and RPC
+/// responses are written to its stdin, RPC requests and event acks are read from its stdout
+#[derive(Debug, Parser)]
+struct Args {
+    /// Plugin ID to connect to the bot as
+    #[arg(long)]
+    id: String,
+
+    /// Event types to subscribe to, e.g. `MessageCreate`
+    #[arg(long = "event", value_parser = parse_event_type)]
+    events: Vec<EventType>,
+
+    /// Topics to subscribe to
+    #[arg(long = "topic")]
+    topics: Vec<String>,
+
+    /// Address of the bot's event publisher, `tcp://host:port` or `unix:///path`
+    #[arg(long, env = "SUBSCRIBER_ADDR")]
+    subscriber_addr: Address,
+
+    /// Address of the bot's RPC server, `tcp://host:port` or `unix:///path`
+    #[arg(long, env = "RPC_ADDR")]
+    rpc_addr: Address,
+
+    /// Token from the bot's last shutdown event, replays the events missed while it restarted
+    #[arg(long, env = "RESTART_TOKEN")]
+    restart_token: Option<String>,
+
+    /// Consumer group shared with other instances, each event then goes to one of them
+    #[arg(long, env = "PLUGIN_GROUP")]
+    group: Option<String>,
+
+    /// How events are spread across the group, `round_robin` or `channel_hash`
+    #[arg(long, env = "PLUGIN_GROUP_BALANCE", value_parser = parse_balance, default_value = "round_robin")]
+    balance: Balance,
+
+    /// Command to run, followed by its arguments
+    #[arg(required = true, trailing_var_arg = true)]
+    command: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum InputLine {
+    Request(RequestLine),
+    // Acknowledges an event that carried an `ack_id`
+    Ack(EventAck),
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestLine {
+    id: Value,
+    request: Value,
+    // Usually the trace of the event that caused the request
+    #[serde(default)]
+    trace: Option<TraceContext>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutputLine {
+    Event {
+        event: Event,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        trace: Option<TraceContext>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        shard: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ack_id: Option<u64>,
+    },
+    Response {
+        id: Value,
+        #[serde(flatten)]
+        result: RpcResult,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RpcResult {
+    Ok(Box<ProtocolResponse>),
+    Error(String),
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+
+    let events = events::connect(
+        args.subscriber_addr.connect().await?,
+        events::HandshakeRequest {
+            id: args.id.clone(),
+            events: args.events.into_iter().collect(),
+            topics: args.topics.into_iter().collect::<HashSet<_>>(),
+            protocol_version: globibot_core::PROTOCOL_VERSION,
+            restart_token: args.restart_token,
+            group: args.group.map(|id| ConsumerGroup {
+                id,
+                balance: args.balance,
+            }),
+        },
+    )
+    .await?;
+    let (rpc, dispatch) = rpc::connect_channel(
+        Default::default(),
+        args.rpc_addr.connect().await?,
+        rpc::HandshakeRequest::new(args.id),
+    )
+    .await?;
+    tokio::spawn(dispatch);
+
+    let (program, program_args) = args
+        .command
+        .split_first()
+        .expect("Command is a required argument");
+    let mut child = Command::new(program)
+        .args(program_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take().expect("Child stdin is piped");
+    let stdout = child.stdout.take().expect("Child stdout is piped");
+
+    let (output_tx, output_rx) = mpsc::unbounded_channel();
+    let (ack_sink, events) = events.split();
+    let (ack_tx, ack_rx) = futures::channel::mpsc::unbounded();
+
+    let forward_events = events.for_each({
+        let output_tx = output_tx.clone();
+        move |event| {
+            let output_tx = output_tx.clone();
+            async move {
+                match event {
+                    Ok(TracedEvent {
+                        event,
+                        trace,
+                        shard,
+                        ack_id,
+                    }) => {
+                        let line = OutputLine::Event {
+                            event,
+                            trace,
+                            shard,
+                            ack_id,
+                        };
+                        output_tx.send(line).ok();
+                    }
+                    Err(why) => warn!("Invalid event: {why}"),
+                }
+            }
+        }
+    });
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status?;
+            debug!("Command exited: {status}");
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        () = forward_events => anyhow::bail!("Event stream ended"),
+        result = ack_rx.map(Ok).forward(ack_sink) => Ok(result?),
+        result = write_output(stdin, output_rx) => result,
+        result = read_requests(stdout, rpc, output_tx, ack_tx) => result,
+    }
+}
+
+async fn write_output(
+    mut stdin: ChildStdin,
+    mut output: mpsc::UnboundedReceiver<OutputLine>,
+) -> anyhow::Result<()> {
+    while let Some(line) = output.recv().await {
+        let mut line = serde_json::to_vec(&line)?;
+        line.push(b'\n');
+        stdin.write_all(&line).await?;
+        stdin.flush().await?;
+    }
+
+    Ok(())
+}
+
+async fn read_requests(
+    stdout: ChildStdout,
+    rpc: ProtocolChannel,
+    output: mpsc::UnboundedSender<OutputLine>,
+    acks: UnboundedSender<EventAck>,
+) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(stdout).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let RequestLine { id, request, trace } = match serde_json::from_str(&line) {
+            Ok(InputLine::Request(request)) => request,
+            Ok(InputLine::Ack(ack)) => {
+                acks.unbounded_send(ack).ok();
+                continue;
+            }
+            Err(why) => {
+                warn!("Ignoring malformed request line: {why}");
+                continue;
+            }
+        };
+
+        let request = match serde_json::from_value::<ProtocolRequest>(request) {
+            Ok(request) => request,
+            Err(why) => {
+                let result = RpcResult::Error(format!("Invalid request: {why}"));
+                output.send(OutputLine::Response { id, result }).ok();
+                continue;
+            }
+        };
+
+        tokio::spawn({
+            let rpc = rpc.clone();
+            let output = output.clone();
+            async move {
+                let mut ctx = rpc::context::current();
+                if let Some(trace) = trace {
+                    ctx.trace_context = trace;
+                }
+                let result = match rpc.call(ctx, request).await {
+                    Ok(response) => RpcResult::Ok(Box::new(response)),
+                    Err(why) => RpcResult::Error(why.to_string()),
+                };
+                output.send(OutputLine::Response { id, result }).ok();
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn parse_event_type(name: &str) -> Result<EventType, serde_json::Error> {
+    serde_json::from_value(Value::String(name.to_owned()))
+}
+
+fn parse_balance(name: &str) -> Result<Balance, serde_json::Error> {
+    serde_json::from_value(Value::String(name.to_owned()))
+}
@@ -1,8 +1,10 @@
 import { writable } from 'svelte/store';
-import type { Plugin } from '../types';
+import type { Plugin, PluginDetails, ShardStatus } from '../types';
 
 class PluginStore {
   plugins = writable<Plugin[]>([]);
+  details = writable<Record<string, PluginDetails>>({});
+  shards = writable<Record<number, ShardStatus>>({});
 
   async fetchPlugins(fetch: typeof window.fetch) {
     const res = await fetch('/api/plugins');
@@ -10,11 +12,36 @@ class PluginStore {
     this.plugins.set(plugins);
   }
 
+  async fetchPluginDetails(fetch: typeof window.fetch, name: string) {
+    const res = await fetch(`/api/plugins/${encodeURIComponent(name)}`);
+    if (!res.ok) {
+      return;
+    }
+    const details: PluginDetails = await res.json();
+    this.details.update((all) => ({ ...all, [name]: details }));
+  }
+
+  async fetchShards(fetch: typeof window.fetch) {
+    const res = await fetch('/api/shards');
+    const shards: ShardStatus[] = await res.json();
+    this.shards.set(Object.fromEntries(shards.map((shard) => [shard.id, shard])));
+  }
+
   #handleEvent(event: MessageEvent) {
     const data: ServerEvent = JSON.parse(event.data);
     if ('RemovedPlugin' in data) {
       const pluginName = data.RemovedPlugin;
       this.plugins.update((plugins) => plugins.filter((p) => p.name !== pluginName));
+      this.details.update((all) => {
+        delete all[pluginName];
+        return all;
+      });
+    } else if ('PluginDetails' in data) {
+      const details = data.PluginDetails;
+      this.details.update((all) => ({ ...all, [details.name]: details }));
+    } else if ('ShardUpdate' in data) {
+      const shard = data.ShardUpdate;
+      this.shards.update((all) => ({ ...all, [shard.id]: shard }));
     } else if ('UpsertedPlugin' in data) {
       const upsertedPlugin = data.UpsertedPlugin;
       this.plugins.update((plugins) => {
@@ -47,6 +74,10 @@ class PluginStore {
   }
 }
 
-type ServerEvent = { UpsertedPlugin: Plugin } | { RemovedPlugin: string };
+type ServerEvent =
+  | { UpsertedPlugin: Plugin }
+  | { RemovedPlugin: string }
+  | { PluginDetails: PluginDetails }
+  | { ShardUpdate: ShardStatus };
 
 export const pluginStore = new PluginStore();
@@ -3,3 +3,78 @@ export interface Plugin {
   has_rpc: boolean;
   has_events: boolean;
 }
+
+export interface SystemTime {
+  secs_since_epoch: number;
+  nanos_since_epoch: number;
+}
+
+export interface Endpoint {
+  connected_at: SystemTime;
+  remote_addr: string | null;
+}
+
+export interface RpcEndpoint extends Endpoint {
+  instance: number | null;
+}
+
+export interface ConsumerGroup {
+  id: string;
+  balance: 'round_robin' | 'channel_hash';
+}
+
+export interface EventsEndpoint extends Endpoint {
+  instance: number;
+  group: ConsumerGroup | null;
+  events: string[];
+  topics: string[];
+  denied_events: string[];
+}
+
+export interface RpcErrorRecord {
+  at: SystemTime;
+  method: string;
+  error: string;
+}
+
+export interface RegisteredCommand {
+  name: string;
+  guild_id: string | null;
+}
+
+export interface PluginDetails {
+  name: string;
+  protocol_version: number | null;
+  rpc: RpcEndpoint[];
+  events: EventsEndpoint[];
+  events_delivered: number;
+  events_dropped: number;
+  rpc_calls: Record<string, number>;
+  last_errors: RpcErrorRecord[];
+  commands: Record<string, RegisteredCommand>;
+}
+
+export type ActivityEntry =
+  | {
+      kind: 'event';
+      at: SystemTime;
+      event_type: string;
+      guild_id: string | null;
+      plugin: string | null;
+      event: unknown;
+    }
+  | {
+      kind: 'rpc';
+      at: SystemTime;
+      plugin: string;
+      method: string;
+      request: unknown;
+      duration: { secs: number; nanos: number };
+      error: string | null;
+    };
+
+export interface ShardStatus {
+  id: number;
+  stage: string;
+  latency: { secs: number; nanos: number } | null;
+}
@@ -4,5 +4,5 @@ import { pluginStore } from '../lib/stores/plugins';
 export const ssr = false;
 
 export const load: LayoutLoad<void> = async ({ fetch }) => {
-  await pluginStore.fetchPlugins(fetch);
+  await Promise.all([pluginStore.fetchPlugins(fetch), pluginStore.fetchShards(fetch)]);
 };
@@ -1,9 +1,10 @@
 import { pluginStore } from '../../../lib/stores/plugins';
 import type { PageLoad } from './$types';
 
-export const load: PageLoad = async ({ params, parent }) => {
+export const load: PageLoad = async ({ params, parent, fetch }) => {
   await parent();
+  await pluginStore.fetchPluginDetails(fetch, params.slug);
   return {
-    plugin: null //$pluginStore.plugins find((p) => p.name === params.slug) ?? null
+    name: params.slug
   };
 };